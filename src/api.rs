@@ -1,11 +1,203 @@
-use std::{thread, time};
+use std::collections::{BTreeMap, HashMap};
+use std::cmp;
 
 use failure::Error;
 use indicatif::ProgressBar;
-use reqwest::Client;
+use regex::Regex;
+use serde::de::DeserializeOwned;
 use url::Url;
 
+use client::{for_each_concurrently, HttpClient};
 use data::Data;
+use error::{Failure, FetchError};
+use meta::{now_timestamp, PageMeta};
+use title::Title;
+use update::record_crawl_start;
+
+/// Max number of page ids per request allowed for non-bot users
+pub(crate) const MAX_PAGEIDS_PER_REQUEST: usize = 50;
+
+/// Parameters to continue a query, e.g. {"apcontinue": "Ore", "continue": "-||"}
+pub type Continuation = BTreeMap<String, String>;
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct MWError {
+    pub code: String,
+    pub info: String,
+}
+
+impl MWError {
+    fn into_fetch_error(self) -> FetchError {
+        match &self.code[..] {
+            "maxlag" => {
+                let secs = parse_maxlag_waiting_time(&self.info).unwrap_or(DEFAULT_MAXLAG_WAIT);
+                FetchError::Maxlag(cmp::max(1, secs))
+            }
+            _ => FetchError::Api {
+                code: self.code,
+                info: self.info,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct MWQueryApiResponse<Q> {
+    pub error: Option<MWError>,
+    #[serde(rename = "continue")]
+    pub _continue: Option<Continuation>,
+    pub query: Option<Q>,
+}
+
+/// Seconds to wait on a maxlag error whose info doesn't tell the lag
+const DEFAULT_MAXLAG_WAIT: u64 = 5;
+
+/// Parse time from maxlag info like this: "Waiting for a database server: 0 seconds lagged."
+fn parse_maxlag_waiting_time(info: &str) -> Option<u64> {
+    lazy_static! {
+        static ref MAXLAG_RE: Regex = Regex::new(r"(?P<time>\d+) seconds lagged.$").unwrap();
+    }
+    MAXLAG_RE.captures(info)?["time"].parse().ok()
+}
+
+/// Client of `action=query` of api.php
+///
+/// It retries requests on maxlag errors and follows the `continue` object of any list, prop
+/// and generator modules, so that each query only has to define the type of its result.
+pub struct QueryClient<'a> {
+    url: String,
+    client: &'a HttpClient,
+    maxlag: i32,
+}
+
+impl<'a> QueryClient<'a> {
+    /// `maxlag` of 5 is recommended. see: https://www.mediawiki.org/wiki/Manual:Maxlag_parameter/ja
+    pub fn new(url: &str, maxlag: i32, client: &'a HttpClient) -> QueryClient<'a> {
+        QueryClient {
+            url: url.to_string(),
+            client,
+            maxlag,
+        }
+    }
+
+    /// Send a query continued by `continuation` and return its result and the continuation
+    /// of the next batch if any
+    pub fn query<Q: DeserializeOwned>(
+        &self,
+        params: &[(&str, &str)],
+        continuation: &Continuation,
+    ) -> Result<(Option<Q>, Option<Continuation>), Error> {
+        let maxlag = self.maxlag.to_string();
+        let mut query = vec![
+            ("action", "query"),
+            ("format", "json"),
+            ("utf8", "true"),
+            ("maxlag", &maxlag[..]),
+        ];
+        query.extend_from_slice(params);
+        query.extend(continuation.iter().map(|(k, v)| (&k[..], &v[..])));
+        let res = self.client.retry(|| {
+            let json: MWQueryApiResponse<Q> = self
+                .client
+                .get(&self.url[..], &query)?
+                .json()
+                .map_err(|e| FetchError::Parse(e.to_string()))?;
+            match json.error {
+                Some(e) => Err(e.into_fetch_error()),
+                None => Ok((json.query, json._continue)),
+            }
+        })?;
+        Ok(res)
+    }
+
+    /// Call `on_batch` with the result of each batch until the query is completed
+    pub fn query_all<Q, F>(&self, params: &[(&str, &str)], mut on_batch: F) -> Result<(), Error>
+    where
+        Q: DeserializeOwned,
+        F: FnMut(Q) -> Result<(), Error>,
+    {
+        self.query_all_from(params, Continuation::new(), |q, _| on_batch(q))
+    }
+
+    /// Same as `query_all` but start from `continuation`, and pass `on_batch` the continuation
+    /// of the next batch too so that the query can be resumed from it
+    pub fn query_all_from<Q, F>(
+        &self,
+        params: &[(&str, &str)],
+        mut continuation: Continuation,
+        mut on_batch: F,
+    ) -> Result<(), Error>
+    where
+        Q: DeserializeOwned,
+        F: FnMut(Q, Option<&Continuation>) -> Result<(), Error>,
+    {
+        loop {
+            let (query, next) = self.query(params, &continuation)?;
+            if let Some(q) = query {
+                on_batch(q, next.as_ref())?;
+            }
+            match next {
+                Some(c) => continuation = c,
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MWSlot {
+    contentmodel: Option<String>,
+    #[serde(rename = "*")]
+    content: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MWRevision {
+    revid: Option<u32>,
+    timestamp: Option<String>,
+    size: Option<u32>,
+    sha1: Option<String>,
+    // MediaWiki < 1.32 puts content on the revision itself instead of in slots
+    contentmodel: Option<String>,
+    #[serde(rename = "*")]
+    content: Option<String>,
+    slots: Option<HashMap<String, MWSlot>>,
+}
+
+impl MWRevision {
+    /// Return the content of the main slot and metadata of the revision
+    fn into_content(self) -> Option<(String, PageMeta)> {
+        let (content, content_model) = match self.slots {
+            Some(mut slots) => {
+                let slot = slots.remove("main")?;
+                (slot.content, slot.contentmodel)
+            }
+            None => (self.content, self.contentmodel),
+        };
+        let meta = PageMeta {
+            revid: self.revid,
+            timestamp: self.timestamp,
+            content_model,
+            size: self.size,
+            sha1: self.sha1,
+            fetched_at: now_timestamp(),
+        };
+        content.map(|c| (c, meta))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MWRevisionsPage {
+    pageid: Option<u32>,
+    title: String,
+    missing: Option<String>,
+    revisions: Option<Vec<MWRevision>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MWRevisionsQuery {
+    pages: HashMap<String, MWRevisionsPage>,
+}
 
 pub fn ensure_endpoint_api_url(url: &str) -> Result<String, Error> {
     let parsed = Url::parse(url)?;
@@ -25,43 +217,266 @@ pub fn ensure_endpoint_index_url(url: &str) -> Result<String, Error> {
     ))
 }
 
-pub fn retrieve_all_markuped_text(url: &str, data_dir: &str) -> Result<(), Error> {
+pub fn retrieve_all_markuped_text(
+    url: &str,
+    data_dir: &str,
+    client: &HttpClient,
+) -> Result<(), Error> {
     let data = Data::new(data_dir);
     // already retrieved
     let ids = data.make_pageid_set_from_markuped_text_files()?;
+    record_crawl_start(&data, &now_timestamp(), !ids.is_empty())?;
     // to retrieve
     let titles = data.load_titles()?;
-    // cache
-    let client = reqwest::Client::new();
     let pb = ProgressBar::new(titles.len() as u64);
-    for title in titles {
-        if !ids.contains(&title.id) {
-            let text = request_markuped_text(url, &title.name[..], &client)?;
-            data.save_markuped_text(title.id, &text[..])?;
-            thread::sleep(time::Duration::from_secs(1));
+    let (retrieved, titles): (Vec<Title>, Vec<Title>) =
+        titles.into_iter().partition(|t| ids.contains(&t.id));
+    pb.inc(retrieved.len() as u64);
+    for_each_concurrently(&titles, client.workers(), |title| {
+        match request_markuped_text(url, &title.name[..], client) {
+            Ok(text) => {
+                data.save_markuped_text(title.id, &text[..])?;
+                data.save_page_meta(title.id, &PageMeta::fetched_now())?;
+            }
+            Err(e) => record_failure(&data, title.id, &e.into())?,
         }
-        pb.inc(1)
-    }
+        pb.inc(1);
+        Ok(())
+    })?;
     pb.finish_with_message("done");
     Ok(())
 }
 
-fn request_markuped_text(url: &str, title: &str, client: &Client) -> Result<String, Error> {
-    let query = &[("action", "raw"), ("title", title), ("utf8", "true")];
-    for _ in 0..5 {
-        let mut res = client.get(url).query(query).send();
-        match res {
-            Ok(ref mut resp) => {
-                return Ok(resp.text()?);
+/// Same as `retrieve_all_markuped_text` but retrieve up to 50 pages per request via API
+///
+/// `url` is the endpoint of api.php, not index.php.
+pub fn retrieve_all_markuped_text_batched(
+    url: &str,
+    data_dir: &str,
+    client: &HttpClient,
+) -> Result<(), Error> {
+    let data = Data::new(data_dir);
+    // already retrieved
+    let ids = data.make_pageid_set_from_markuped_text_files()?;
+    record_crawl_start(&data, &now_timestamp(), !ids.is_empty())?;
+    // to retrieve
+    let titles = data.load_titles()?;
+    let pageids: Vec<u32> = titles
+        .iter()
+        .map(|t| t.id)
+        .filter(|id| !ids.contains(id))
+        .collect();
+    // recommended. see: https://www.mediawiki.org/wiki/Manual:Maxlag_parameter/ja
+    let query_client = QueryClient::new(url, 5, client);
+    let pb = ProgressBar::new(titles.len() as u64);
+    pb.inc((titles.len() - pageids.len()) as u64);
+    let chunks: Vec<&[u32]> = pageids.chunks(MAX_PAGEIDS_PER_REQUEST).collect();
+    for_each_concurrently(&chunks, client.workers(), |chunk| {
+        for (pageid, res) in request_markuped_texts(&query_client, chunk, &data)? {
+            if let Ok((text, meta)) = res {
+                data.save_markuped_text(pageid, &text[..])?;
+                data.save_page_meta(pageid, &meta)?;
             }
-            Err(e) => {
-                eprintln!("unexpected error: {:?}", e);
-                eprintln!("retry after 1 sec...");
-                thread::sleep(time::Duration::from_secs(1));
+        }
+        pb.inc(chunk.len() as u64);
+        Ok(())
+    })?;
+    pb.finish_with_message("done");
+    Ok(())
+}
+
+/// Page id with its content and revision metadata or why it couldn't be fetched
+pub(crate) type FetchedText = (u32, Result<(String, PageMeta), FetchError>);
+
+/// Report a page which couldn't be fetched and record it to the failures log
+pub(crate) fn record_failure(data: &Data, pageid: u32, e: &Error) -> Result<(), Error> {
+    eprintln!("page {} failed: {}", pageid, e);
+    data.save_failure(&Failure::new(pageid, e))
+}
+
+/// Return the latest wikitext of each page with its page id and revision metadata
+///
+/// Pages which couldn't be fetched are recorded to the failures log of `data`, including all
+/// of `pageids` if the request itself failed.
+pub(crate) fn request_markuped_texts(
+    client: &QueryClient,
+    pageids: &[u32],
+    data: &Data,
+) -> Result<Vec<FetchedText>, Error> {
+    let joined_pageids = pageids
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<String>>()
+        .join("|");
+    let params = &[
+        ("prop", "revisions"),
+        ("rvprop", "content|ids|timestamp|size|sha1|contentmodel"),
+        ("rvslots", "main"),
+        ("pageids", &joined_pageids[..]),
+    ];
+    let mut texts = BTreeMap::new();
+    // large pages may be put off to following requests, which list all pages again
+    let res = client.query_all(params, |query| {
+        parse_revisions(query, &mut texts);
+        Ok(())
+    });
+    if let Err(e) = res {
+        for &pageid in pageids {
+            record_failure(data, pageid, &e)?;
+        }
+        return Ok(Vec::new());
+    }
+    for (pageid, res) in &texts {
+        if let Err(e) = res {
+            record_failure(data, *pageid, &format_err!("{}", e))?;
+        }
+    }
+    Ok(texts.into_iter().collect())
+}
+
+/// Merge the content of each page in a batch into `texts`, or why it is absent
+///
+/// A page without revisions in a batch stays fetched if an earlier batch had its content, and
+/// a later batch may still bring it; it is missing only if no batch does.
+fn parse_revisions(
+    query: MWRevisionsQuery,
+    texts: &mut BTreeMap<u32, Result<(String, PageMeta), FetchError>>,
+) {
+    for page in query.pages.into_values() {
+        let pageid = match page.pageid {
+            Some(pageid) => pageid,
+            None => {
+                eprintln!("page \"{}\" is missing", page.title);
+                continue;
+            }
+        };
+        let content = page
+            .revisions
+            .and_then(|revs| revs.into_iter().next())
+            .and_then(MWRevision::into_content);
+        let title = page.title;
+        match content {
+            _ if page.missing.is_some() => {
+                texts.insert(pageid, Err(FetchError::MissingPage(title)));
+            }
+            Some(content) => {
+                texts.insert(pageid, Ok(content));
+            }
+            None => {
+                texts
+                    .entry(pageid)
+                    .or_insert_with(|| Err(FetchError::MissingPage(title)));
             }
         }
     }
-    Err(format_err!("retried 5 times but can't receive"))
+}
+
+fn request_markuped_text(url: &str, title: &str, client: &HttpClient) -> Result<String, FetchError> {
+    let query = &[("action", "raw"), ("title", title), ("utf8", "true")];
+    client.retry(|| match client.get(url, query) {
+        Ok(mut resp) => Ok(resp.text()?),
+        // otherwise the error page would be saved as wikitext
+        Err(FetchError::HttpStatus { status: 404, .. }) => {
+            Err(FetchError::MissingPage(title.to_string()))
+        }
+        Err(e) => Err(e),
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct MWExpandTemplates {
+    wikitext: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct MWExpandTemplatesApiResponse {
+    error: Option<MWError>,
+    expandtemplates: Option<MWExpandTemplates>,
+}
+
+/// Expand templates in the markuped text of each page and save it to `expanded`
+///
+/// Markuped texts must be retrieved beforehand. Pages already expanded are skipped.
+pub fn retrieve_all_expanded_text(
+    url: &str,
+    data_dir: &str,
+    client: &HttpClient,
+) -> Result<(), Error> {
+    let data = Data::new(data_dir);
+    let names: HashMap<u32, String> = data
+        .load_titles()?
+        .into_iter()
+        .map(|t| (t.id, t.name))
+        .collect();
+    let mut pageids: Vec<u32> = data
+        .make_pageid_set_from_markuped_text_files()?
+        .into_iter()
+        .filter(|&pageid| !data.has_expanded_text(pageid))
+        .collect();
+    pageids.sort();
+    let pb = ProgressBar::new(pageids.len() as u64);
+    for_each_concurrently(&pageids, client.workers(), |&pageid| {
+        expand_markuped_text(url, &data, pageid, names.get(&pageid), client)?;
+        pb.inc(1);
+        Ok(())
+    })?;
+    pb.finish_with_message("done");
+    Ok(())
+}
+
+/// Expand templates in the markuped text of a page titled `title` and save it to `expanded`
+///
+/// A page which couldn't be expanded is recorded to the failures log.
+pub(crate) fn expand_markuped_text(
+    url: &str,
+    data: &Data,
+    pageid: u32,
+    title: Option<&String>,
+    client: &HttpClient,
+) -> Result<(), Error> {
+    let res = match title {
+        Some(title) => {
+            let text = data.load_markuped_text(pageid)?;
+            request_expanded_text(url, title, &text[..], client).map_err(Error::from)
+        }
+        None => Err(format_err!("page {} is not in titles", pageid)),
+    };
+    match res {
+        Ok(expanded) => Ok(data.save_expanded_text(pageid, &expanded[..])?),
+        Err(e) => record_failure(data, pageid, &e),
+    }
+}
+
+/// Return `text` whose templates are expanded as if it were the content of `title`
+///
+/// see: https://www.mediawiki.org/wiki/API:Expandtemplates
+fn request_expanded_text(
+    url: &str,
+    title: &str,
+    text: &str,
+    client: &HttpClient,
+) -> Result<String, FetchError> {
+    let form = &[
+        ("action", "expandtemplates"),
+        ("format", "json"),
+        ("utf8", "true"),
+        ("maxlag", "5"),
+        ("prop", "wikitext"),
+        ("title", title),
+        ("text", text),
+    ];
+    client.retry(|| {
+        // POST since texts may be too long for a URL
+        let json: MWExpandTemplatesApiResponse = client
+            .post(url, form)?
+            .json()
+            .map_err(|e| FetchError::Parse(e.to_string()))?;
+        match (json.error, json.expandtemplates) {
+            (Some(e), _) => Err(e.into_fetch_error()),
+            (None, Some(expanded)) => Ok(expanded.wikitext),
+            (None, None) => Err(FetchError::Parse("no expandtemplates in response".to_string())),
+        }
+    })
 }
 
 #[cfg(test)]
@@ -91,4 +506,99 @@ mod tests {
         assert_eq!(ensure_endpoint_index_url(url_pre)?, url_expected);
         Ok(())
     }
+
+    #[test]
+    fn test_parse_maxlag_waiting_time() {
+        assert_eq!(
+            parse_maxlag_waiting_time("Waiting for a database server: 0 seconds lagged."),
+            Some(0)
+        );
+        assert_eq!(
+            parse_maxlag_waiting_time("Waiting for a database server: 10 seconds lagged."),
+            Some(10)
+        );
+        assert_eq!(parse_maxlag_waiting_time("Waiting for db1234: 1.5 seconds lagged"), None);
+        let error = MWError {
+            code: "maxlag".to_string(),
+            info: "Waiting for replicas".to_string(),
+        };
+        match error.into_fetch_error() {
+            FetchError::Maxlag(secs) => assert_eq!(secs, DEFAULT_MAXLAG_WAIT),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse_expanded_text() -> Result<(), Error> {
+        let json: MWExpandTemplatesApiResponse = serde_json::from_str(
+            r#"{"expandtemplates": {"wikitext": "[[Iron Ore|Iron]] is found in the [[Overworld]]"}}"#,
+        )?;
+        assert_eq!(
+            json.expandtemplates.unwrap().wikitext,
+            "[[Iron Ore|Iron]] is found in the [[Overworld]]"
+        );
+        let json: MWExpandTemplatesApiResponse = serde_json::from_str(
+            r#"{"error": {"code": "maxlag", "info": "Waiting for a database server: 3 seconds lagged."}}"#,
+        )?;
+        match json.error.unwrap().into_fetch_error() {
+            FetchError::Maxlag(secs) => assert_eq!(secs, 3),
+            e => panic!("unexpected error: {}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_revisions() -> Result<(), Error> {
+        let json = r#"{"continue":{"rvcontinue":"3020|41234","continue":"||"},"query":{"pages":{
+            "3020":{"pageid":3020,"ns":0,"title":"Ore","revisions":[
+                {"revid":41234,"parentid":41000,"timestamp":"2018-11-01T00:00:00Z","size":20,
+                 "sha1":"0a1b2c","slots":{"main":
+                    {"contentmodel":"wikitext","contentformat":"text/x-wiki","*":"'''Ores''' are rare."}}}
+            ]},
+            "7315":{"pageid":7315,"ns":0,"title":"Ore Block","revisions":[
+                {"revid":1,"parentid":0,"timestamp":"2018-11-01T00:00:00Z","contentformat":"text/x-wiki",
+                 "contentmodel":"wikitext","*":"Legacy format"}
+            ]},
+            "24709":{"pageid":24709,"ns":0,"title":"Ore/video"},
+            "-1":{"ns":0,"title":"Deleted","missing":""}
+        }}}"#;
+        let json: MWQueryApiResponse<MWRevisionsQuery> = serde_json::from_str(json)?;
+        assert_eq!(json._continue.unwrap()["rvcontinue"], "3020|41234");
+        let mut merged = BTreeMap::new();
+        parse_revisions(json.query.unwrap(), &mut merged);
+        // the following batch lists the pages again, only with the ones put off
+        let json = r#"{"query":{"pages":{
+            "3020":{"pageid":3020,"ns":0,"title":"Ore"},
+            "7315":{"pageid":7315,"ns":0,"title":"Ore Block"},
+            "24709":{"pageid":24709,"ns":0,"title":"Ore/video","revisions":[
+                {"revid":5,"parentid":4,"timestamp":"2018-11-02T00:00:00Z","slots":{"main":
+                    {"contentmodel":"wikitext","contentformat":"text/x-wiki","*":"A video."}}}
+            ]},
+            "24710":{"pageid":24710,"ns":0,"title":"Ore/gallery"}
+        }}}"#;
+        let json: MWQueryApiResponse<MWRevisionsQuery> = serde_json::from_str(json)?;
+        parse_revisions(json.query.unwrap(), &mut merged);
+        let texts: Vec<FetchedText> = merged.into_iter().collect();
+        assert_eq!(texts.len(), 4);
+        assert_eq!(texts[0].0, 3020);
+        let (text, meta) = texts[0].1.as_ref().unwrap();
+        assert_eq!(text, "'''Ores''' are rare.");
+        assert_eq!(meta.revid, Some(41234));
+        assert_eq!(meta.timestamp, Some("2018-11-01T00:00:00Z".to_string()));
+        assert_eq!(meta.content_model, Some("wikitext".to_string()));
+        assert_eq!(meta.size, Some(20));
+        assert_eq!(meta.sha1, Some("0a1b2c".to_string()));
+        assert_eq!(texts[1].0, 7315);
+        let (text, meta) = texts[1].1.as_ref().unwrap();
+        assert_eq!(text, "Legacy format");
+        assert_eq!(meta.content_model, Some("wikitext".to_string()));
+        assert_eq!(texts[2].0, 24709);
+        assert_eq!(texts[2].1.as_ref().unwrap().0, "A video.");
+        assert_eq!(texts[3].0, 24710);
+        match texts[3].1 {
+            Err(FetchError::MissingPage(ref title)) => assert_eq!(title, "Ore/gallery"),
+            _ => panic!("Ore/gallery has no revision in any batch"),
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,102 @@
+use failure::Error;
+
+use data::{parse_pageid, Data};
+use parser::{Doc, Token, Tokenizer};
+
+/// Label attached to B-/I-/L-/U- tags
+///
+/// Entities have no type yet, so every span shares this label.
+const ENTITY_LABEL: &str = "ENT";
+
+/// Return a BILUO tag for each token
+///
+/// A token is regarded as a part of an entity if their byte spans overlap.
+fn tag_tokens(tokens: &[Token], spans: &[(usize, usize)]) -> Vec<String> {
+    let mut tags = vec!["O".to_string(); tokens.len()];
+    for &(start, end) in spans {
+        let indices: Vec<usize> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.start < end && start < t.end)
+            .map(|(i, _)| i)
+            .collect();
+        match indices.len() {
+            0 => (),
+            1 => tags[indices[0]] = format!("U-{}", ENTITY_LABEL),
+            n => {
+                tags[indices[0]] = format!("B-{}", ENTITY_LABEL);
+                for &i in &indices[1..n - 1] {
+                    tags[i] = format!("I-{}", ENTITY_LABEL);
+                }
+                tags[indices[n - 1]] = format!("L-{}", ENTITY_LABEL);
+            }
+        }
+    }
+    tags
+}
+
+/// Convert Doc into TSV with a token and its tag per line
+///
+/// Each line of `Doc.text` becomes a block separated by an empty line.
+pub fn to_biluo_tsv(doc: &Doc, tokenizer: &dyn Tokenizer) -> Result<String, Error> {
+    let tokens = tokenizer.tokenize(&doc.text);
+    let tags = tag_tokens(&tokens, &doc.entity_byte_spans()?);
+    let mut tsv = String::new();
+    let mut prev_end = 0;
+    for (token, tag) in tokens.iter().zip(tags.iter()) {
+        if prev_end > 0 && doc.text[prev_end..token.start].contains('\n') {
+            tsv.push('\n');
+        }
+        tsv.push_str(token.text);
+        tsv.push('\t');
+        tsv.push_str(tag);
+        tsv.push('\n');
+        prev_end = token.end;
+    }
+    Ok(tsv)
+}
+
+pub fn export_all_biluo(data_dir: &str, tokenizer: &dyn Tokenizer) -> Result<(), Error> {
+    let data = Data::new(data_dir);
+    for entry in data.parsed_text_files()? {
+        match entry {
+            Ok(path) => {
+                let pageid = parse_pageid(&path);
+                let doc = data.load_parsed_text(pageid)?;
+                data.save_biluo(pageid, &to_biluo_tsv(&doc, tokenizer)?)?;
+            }
+            Err(e) => {
+                eprintln!("{:?}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::{CjkTokenizer, OffsetUnit, WhitespaceTokenizer};
+
+    #[test]
+    fn test_to_biluo_tsv() -> Result<(), Error> {
+        let doc =
+            Doc::parse("Smelt [[Iron Ore]] into [[Iron Ingot|ingots]].\n\nUse a [[furnace]].")?;
+        let expected = "Smelt\tO\nIron\tB-ENT\nOre\tL-ENT\ninto\tO\ningots\tU-ENT\n.\tO\n\n\
+                        Use\tO\na\tO\nfurnace\tU-ENT\n.\tO\n";
+        assert_eq!(to_biluo_tsv(&doc, &WhitespaceTokenizer)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_biluo_tsv_cjk() -> Result<(), Error> {
+        let mut doc = Doc::parse("[[鉄インゴット|鉄]]は[[かまど]]で作る。")?;
+        let expected =
+            "鉄\tU-ENT\nは\tO\nか\tB-ENT\nま\tI-ENT\nど\tL-ENT\nで\tO\n作\tO\nる\tO\n。\tO\n";
+        assert_eq!(to_biluo_tsv(&doc, &CjkTokenizer)?, expected);
+
+        doc.convert_offsets(OffsetUnit::Char)?;
+        assert_eq!(to_biluo_tsv(&doc, &CjkTokenizer)?, expected);
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+#[macro_use]
 extern crate failure;
 extern crate mwkb;
 
@@ -5,10 +6,31 @@ use std::env;
 
 use failure::Error;
 
-use mwkb::api::{ensure_endpoint_index_url, retrieve_all_markuped_text};
+use mwkb::api::{
+    ensure_endpoint_api_url, ensure_endpoint_index_url, retrieve_all_expanded_text,
+    retrieve_all_markuped_text, retrieve_all_markuped_text_batched,
+};
+use mwkb::client::{ClientConfig, HttpClient};
 
 fn main() -> Result<(), Error> {
-    let args: Vec<String> = env::args().collect();
-    let url = ensure_endpoint_index_url(&args[1])?;
-    retrieve_all_markuped_text(&url[..], &args[2])
+    let mut args: Vec<String> = env::args().collect();
+    let config = ClientConfig::from_env_and_args(&mut args)?;
+    let client = HttpClient::new(&config)?;
+    // "raw" requests index.php per page, "batch" requests api.php per 50 pages, "expanded"
+    // expands templates in retrieved pages
+    match args.get(3).map_or("raw", String::as_str) {
+        "raw" => {
+            let url = ensure_endpoint_index_url(&args[1])?;
+            retrieve_all_markuped_text(&url[..], &args[2], &client)
+        }
+        "batch" => {
+            let url = ensure_endpoint_api_url(&args[1])?;
+            retrieve_all_markuped_text_batched(&url[..], &args[2], &client)
+        }
+        "expanded" => {
+            let url = ensure_endpoint_api_url(&args[1])?;
+            retrieve_all_expanded_text(&url[..], &args[2], &client)
+        }
+        mode => Err(format_err!("unknown mode: {}", mode)),
+    }
 }
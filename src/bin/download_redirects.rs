@@ -0,0 +1,28 @@
+extern crate failure;
+extern crate mwkb;
+
+use std::env;
+
+use failure::Error;
+
+use mwkb::api::ensure_endpoint_api_url;
+use mwkb::client::{ClientConfig, HttpClient};
+use mwkb::data::Data;
+use mwkb::redirect::retrieve_all_redirects;
+use mwkb::title::{request_interwikis, request_namespaces, select_namespaces};
+
+fn main() -> Result<(), Error> {
+    let mut args: Vec<String> = env::args().collect();
+    let config = ClientConfig::from_env_and_args(&mut args)?;
+    let url = ensure_endpoint_api_url(&args[1])?;
+    let data = Data::new(&args[2]);
+    // comma-separated ids or names, e.g. "Main,Category,10"
+    let specs: Vec<&str> = args.get(3).map_or("0", String::as_str).split(',').collect();
+    let client = HttpClient::new(&config)?;
+    let table = request_namespaces(&url[..], &client)?;
+    data.save_namespaces(&table)?;
+    data.save_interwikis(&request_interwikis(&url[..], &client)?)?;
+    let namespaces = select_namespaces(&table, &specs)?;
+    let redirects = retrieve_all_redirects(&url[..], &namespaces, &client)?;
+    data.save_redirects(&redirects)
+}
@@ -6,20 +6,21 @@ use std::env;
 use failure::Error;
 
 use mwkb::api::ensure_endpoint_api_url;
+use mwkb::client::{ClientConfig, HttpClient};
 use mwkb::data::Data;
-use mwkb::title::retrieve_all_titles;
+use mwkb::title::{request_interwikis, request_namespaces, retrieve_all_titles, select_namespaces};
 
 fn main() -> Result<(), Error> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let config = ClientConfig::from_env_and_args(&mut args)?;
     let url = ensure_endpoint_api_url(&args[1])?;
     let data = Data::new(&args[2]);
-    let mut titles = if data.title_file.exists() {
-        data.load_titles()?
-    } else {
-        Vec::new()
-    };
-    let res = retrieve_all_titles(&mut titles, &url[..]);
-    data.save_titles(&titles)?;
-
-    res
+    // comma-separated ids or names, e.g. "Main,Category,10"
+    let specs: Vec<&str> = args.get(3).map_or("0", String::as_str).split(',').collect();
+    let client = HttpClient::new(&config)?;
+    let table = request_namespaces(&url[..], &client)?;
+    data.save_namespaces(&table)?;
+    data.save_interwikis(&request_interwikis(&url[..], &client)?)?;
+    let namespaces = select_namespaces(&table, &specs)?;
+    retrieve_all_titles(&url[..], &args[2], &namespaces, &client)
 }
@@ -0,0 +1,16 @@
+extern crate failure;
+extern crate mwkb;
+
+use std::env;
+
+use failure::Error;
+
+use mwkb::biluo::export_all_biluo;
+use mwkb::parser::tokenizer_from_name;
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+    // "whitespace" or "cjk"
+    let tokenizer = tokenizer_from_name(args.get(2).map_or("whitespace", String::as_str))?;
+    export_all_biluo(&args[1], tokenizer.as_ref())
+}
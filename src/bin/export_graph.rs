@@ -0,0 +1,14 @@
+extern crate failure;
+extern crate mwkb;
+
+use std::env;
+
+use failure::Error;
+
+use mwkb::graph::export_graph;
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+    // prefix of IRIs of pages and predicates, e.g. "https://minecraft.gamepedia.com/mwkb/"
+    export_graph(&args[1], args.get(2).map_or("urn:mwkb:", String::as_str))
+}
@@ -0,0 +1,15 @@
+extern crate failure;
+extern crate mwkb;
+
+use std::env;
+
+use failure::Error;
+
+use mwkb::dump::import_dump;
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+    // comma-separated ids or names, e.g. "Main,Category,10"
+    let specs: Vec<&str> = args.get(3).map_or("0", String::as_str).split(',').collect();
+    import_dump(&args[1], &args[2], &specs)
+}
@@ -0,0 +1,13 @@
+extern crate failure;
+extern crate mwkb;
+
+use std::env;
+
+use failure::Error;
+
+use mwkb::link::link_all_entities;
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+    link_all_entities(&args[1])
+}
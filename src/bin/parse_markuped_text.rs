@@ -1,13 +1,19 @@
 extern crate failure;
 extern crate mwkb;
 
-use std::env;
+use std::{env, thread};
 
 use failure::Error;
 
-use mwkb::parser::parse_all_markuped_text;
+use mwkb::parser::{parse_all_markuped_text, OffsetUnit};
 
 fn main() -> Result<(), Error> {
     let args: Vec<String> = env::args().collect();
-    parse_all_markuped_text(&args[1])
+    // "byte", "char" or "utf16"
+    let offset_unit = OffsetUnit::from_name(args.get(2).map_or("byte", String::as_str))?;
+    let workers = match args.get(3) {
+        Some(workers) => workers.parse()?,
+        None => thread::available_parallelism().map_or(1, usize::from),
+    };
+    parse_all_markuped_text(&args[1], offset_unit, workers)
 }
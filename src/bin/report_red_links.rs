@@ -0,0 +1,16 @@
+extern crate failure;
+extern crate mwkb;
+
+use std::env;
+
+use failure::Error;
+
+use mwkb::link::report_red_links;
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+    let red_links = report_red_links(&args[1])?;
+    let total: u32 = red_links.iter().map(|r| r.count).sum();
+    eprintln!("{} links to {} missing targets", total, red_links.len());
+    Ok(())
+}
@@ -0,0 +1,20 @@
+extern crate failure;
+extern crate mwkb;
+
+use std::env;
+
+use failure::Error;
+
+use mwkb::api::ensure_endpoint_api_url;
+use mwkb::client::{ClientConfig, HttpClient};
+use mwkb::update::update_markuped_text;
+
+fn main() -> Result<(), Error> {
+    let mut args: Vec<String> = env::args().collect();
+    let config = ClientConfig::from_env_and_args(&mut args)?;
+    let url = ensure_endpoint_api_url(&args[1])?;
+    // e.g. "2018-11-01T00:00:00Z", the last crawl time by default
+    let since = args.get(3).map(String::as_str);
+    let client = HttpClient::new(&config)?;
+    update_markuped_text(&url[..], &args[2], since, &client)
+}
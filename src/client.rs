@@ -0,0 +1,428 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{cmp, env, thread};
+
+use failure::Error;
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER, USER_AGENT};
+use reqwest::{Client, Response};
+
+use error::FetchError;
+
+/// Exponential backoff with jitter for retryable errors
+///
+/// see: https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// Delay before the first retry
+    pub base: Duration,
+    /// Upper bound of delays
+    pub max: Duration,
+    pub retries: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            retries: 5,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the `attempt`-th retry counted from 0
+    ///
+    /// It doubles per attempt and is randomized between its half and itself so that workers
+    /// don't retry at once.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base
+            .checked_mul(1 << cmp::min(attempt, 16))
+            .map_or(self.max, |d| cmp::min(d, self.max));
+        exp.mul_f64(rand::thread_rng().gen_range(0.5, 1.0))
+    }
+
+    /// Call `f` until it succeeds, fails with a non-retryable error or runs out of retries
+    ///
+    /// Waits at least as long as the server asks.
+    pub fn retry<T, F>(&self, mut f: F) -> Result<T, FetchError>
+    where
+        F: FnMut() -> Result<T, FetchError>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(ref e) if e.is_retryable() && attempt < self.retries => {
+                    let delay = self.delay(attempt);
+                    let delay = e
+                        .wait_secs()
+                        .map_or(delay, |secs| cmp::max(delay, Duration::from_secs(secs)));
+                    eprintln!("{}: retry after {:.1} secs", e, delay.as_secs_f64());
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// Politeness settings shared by all requests to a wiki
+///
+/// see: https://www.mediawiki.org/wiki/API:Etiquette
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Max number of requests per second summed over all workers
+    pub requests_per_sec: f64,
+    /// Number of workers sending requests concurrently
+    pub workers: usize,
+    /// Should tell how to contact you. see: https://meta.wikimedia.org/wiki/User-Agent_policy
+    pub user_agent: String,
+    pub backoff: Backoff,
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            requests_per_sec: 1.0,
+            workers: 1,
+            user_agent: format!(
+                "mwkb/{} (https://github.com/reiyw/mwkb)",
+                env!("CARGO_PKG_VERSION")
+            ),
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Override the default with `MWKB_REQUESTS_PER_SEC`, `MWKB_WORKERS` and
+    /// `MWKB_USER_AGENT` environment variables
+    pub fn from_env() -> Result<ClientConfig, Error> {
+        let mut config = ClientConfig::default();
+        if let Ok(rps) = env::var("MWKB_REQUESTS_PER_SEC") {
+            config.requests_per_sec = rps.parse()?;
+        }
+        if let Ok(workers) = env::var("MWKB_WORKERS") {
+            config.workers = workers.parse()?;
+        }
+        if let Ok(user_agent) = env::var("MWKB_USER_AGENT") {
+            config.user_agent = user_agent;
+        }
+        config.validate()
+    }
+
+    /// Same as `from_env` but also take `--requests-per-sec`, `--workers` and `--user-agent`
+    /// options out of command line arguments, which override the environment variables
+    ///
+    /// Options are written as `--workers 4` or `--workers=4`.
+    pub fn from_env_and_args(args: &mut Vec<String>) -> Result<ClientConfig, Error> {
+        let mut config = ClientConfig::from_env()?;
+        let mut rest = Vec::new();
+        let mut iter = args.drain(..);
+        while let Some(arg) = iter.next() {
+            let (name, value) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => {
+                    (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+            if !["--requests-per-sec", "--workers", "--user-agent"].contains(&&name[..]) {
+                rest.push(arg);
+                continue;
+            }
+            let value = match value.or_else(|| iter.next()) {
+                Some(value) => value,
+                None => return Err(format_err!("no value for {}", name)),
+            };
+            match &name[..] {
+                "--requests-per-sec" => config.requests_per_sec = value.parse()?,
+                "--workers" => config.workers = value.parse()?,
+                _ => config.user_agent = value,
+            }
+        }
+        drop(iter);
+        *args = rest;
+        config.validate()
+    }
+
+    fn validate(self) -> Result<ClientConfig, Error> {
+        let rps = self.requests_per_sec;
+        if !rps.is_finite() || rps <= 0.0 || self.workers == 0 {
+            return Err(format_err!("invalid client config: {:?}", self));
+        }
+        Ok(self)
+    }
+}
+
+/// HTTP client limiting the request rate over all threads sharing it
+pub struct HttpClient {
+    client: Client,
+    interval: Duration,
+    workers: usize,
+    backoff: Backoff,
+    /// When the next request is allowed
+    next_request: Mutex<Instant>,
+}
+
+impl HttpClient {
+    pub fn new(config: &ClientConfig) -> Result<HttpClient, Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent[..])?);
+        Ok(HttpClient {
+            client: Client::builder().default_headers(headers).build()?,
+            interval: Duration::from_secs_f64(1.0 / config.requests_per_sec),
+            workers: config.workers,
+            backoff: config.backoff.clone(),
+            next_request: Mutex::new(Instant::now()),
+        })
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Put off requests of all workers until `wait` has passed, as the server asked
+    fn defer(&self, wait: Duration) {
+        let mut next_request = self.next_request.lock().unwrap();
+        *next_request = cmp::max(*next_request, Instant::now() + wait);
+    }
+
+    /// Block until the rate limit allows a request
+    fn wait(&self) {
+        let wait = {
+            let mut next_request = self.next_request.lock().unwrap();
+            let now = Instant::now();
+            let scheduled = cmp::max(now, *next_request);
+            *next_request = scheduled + self.interval;
+            scheduled - now
+        };
+        thread::sleep(wait);
+    }
+
+    /// Send a GET request once
+    ///
+    /// Responses other than 2xx are turned into `FetchError::HttpStatus`.
+    pub fn get(&self, url: &str, query: &[(&str, &str)]) -> Result<Response, FetchError> {
+        self.wait();
+        check_status(self.client.get(url).query(query).send()?)
+    }
+
+    /// Send a POST request of a form once, for parameters too long for a URL
+    pub fn post(&self, url: &str, form: &[(&str, &str)]) -> Result<Response, FetchError> {
+        self.wait();
+        check_status(self.client.post(url).form(form).send()?)
+    }
+
+    /// Same as `Backoff::retry` with the backoff of the config
+    ///
+    /// When the server asks to wait by `Retry-After` or maxlag, the other workers wait too.
+    pub fn retry<T, F>(&self, mut f: F) -> Result<T, FetchError>
+    where
+        F: FnMut() -> Result<T, FetchError>,
+    {
+        self.backoff.retry(|| {
+            let res = f();
+            if let Some(secs) = res.as_ref().err().and_then(FetchError::wait_secs) {
+                self.defer(Duration::from_secs(secs));
+            }
+            res
+        })
+    }
+}
+
+/// Turn responses other than 2xx into `FetchError::HttpStatus`
+fn check_status(resp: Response) -> Result<Response, FetchError> {
+    let status = resp.status();
+    if status.is_success() {
+        Ok(resp)
+    } else {
+        Err(FetchError::HttpStatus {
+            status: status.as_u16(),
+            retry_after: parse_retry_after(resp.headers()),
+        })
+    }
+}
+
+/// Return seconds of Retry-After header, which may also be an HTTP date but MediaWiki
+/// always sends seconds
+fn parse_retry_after(headers: &HeaderMap) -> Option<u64> {
+    headers.get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Call `f` with each item on `workers` threads, stopping at the first error
+pub fn for_each_concurrently<T, F>(items: &[T], workers: usize, f: F) -> Result<(), Error>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<(), Error> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..cmp::max(1, workers))
+            .map(|_| {
+                s.spawn(|| {
+                    while !failed.load(Ordering::SeqCst) {
+                        let item = match items.get(next.fetch_add(1, Ordering::SeqCst)) {
+                            Some(item) => item,
+                            None => break,
+                        };
+                        if let Err(e) = f(item) {
+                            failed.store(true, Ordering::SeqCst);
+                            return Err(e);
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Result<Vec<()>, Error>>()
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() -> Result<(), Error> {
+        let config = ClientConfig {
+            requests_per_sec: 20.0,
+            ..ClientConfig::default()
+        };
+        let client = HttpClient::new(&config)?;
+        let start = Instant::now();
+        for _ in 0..5 {
+            client.wait();
+        }
+        // the first request doesn't wait
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_after_defers_all_workers() -> Result<(), Error> {
+        let config = ClientConfig {
+            requests_per_sec: 1000.0,
+            backoff: Backoff {
+                base: Duration::from_millis(1),
+                max: Duration::from_millis(1),
+                retries: 1,
+            },
+            ..ClientConfig::default()
+        };
+        let client = HttpClient::new(&config)?;
+        let start = Instant::now();
+        let mut calls = 0;
+        let res = client.retry(|| {
+            calls += 1;
+            match calls {
+                1 => Err(FetchError::HttpStatus {
+                    status: 429,
+                    retry_after: Some(1),
+                }),
+                _ => Ok(()),
+            }
+        });
+        assert!(res.is_ok());
+        // a request of another worker waits as well
+        client.wait();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_config_from_args() -> Result<(), Error> {
+        let mut args: Vec<String> = [
+            "bin",
+            "--workers",
+            "4",
+            "url",
+            "--user-agent=Bot/1.0",
+            "dir",
+        ].iter()
+        .map(|a| a.to_string())
+        .collect();
+        let config = ClientConfig::from_env_and_args(&mut args)?;
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.user_agent, "Bot/1.0");
+        assert_eq!(args, vec!["bin", "url", "dir"]);
+
+        let mut args = vec!["bin".to_string(), "--workers=0".to_string()];
+        assert!(ClientConfig::from_env_and_args(&mut args).is_err());
+        let mut args = vec!["bin".to_string(), "--requests-per-sec".to_string()];
+        assert!(ClientConfig::from_env_and_args(&mut args).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff {
+            base: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+            retries: 3,
+        };
+        let delay = backoff.delay(1);
+        assert!(Duration::from_millis(10) <= delay && delay <= Duration::from_millis(20));
+        assert!(backoff.delay(10) <= Duration::from_millis(50));
+
+        let mut calls = 0;
+        let res: Result<(), FetchError> = backoff.retry(|| {
+            calls += 1;
+            Err(FetchError::Network("timed out".to_string()))
+        });
+        assert!(res.is_err());
+        assert_eq!(calls, 4);
+
+        let mut calls = 0;
+        let res: Result<(), FetchError> = backoff.retry(|| {
+            calls += 1;
+            Err(FetchError::MissingPage("Ore".to_string()))
+        });
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("5"));
+        assert_eq!(parse_retry_after(&headers), Some(5));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(parse_retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_for_each_concurrently() {
+        let items: Vec<u32> = (1..=100).collect();
+        let sum = AtomicU32::new(0);
+        let res = for_each_concurrently(&items, 4, |i| {
+            sum.fetch_add(*i, Ordering::SeqCst);
+            Ok(())
+        });
+        assert!(res.is_ok());
+        assert_eq!(sum.load(Ordering::SeqCst), 5050);
+
+        let res = for_each_concurrently(&items, 4, |i| {
+            if *i == 50 {
+                Err(format_err!("failed at {}", i))
+            } else {
+                Ok(())
+            }
+        });
+        assert!(res.is_err());
+    }
+}
@@ -2,12 +2,18 @@ use std::collections::HashSet;
 use std::fs;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use failure::Error;
 use glob::{glob, Paths, PatternError};
 
-use parser::Doc;
-use title::{load_titles, save_titles, Title};
+use error::Failure;
+use link::{save_red_links, RedLink};
+use meta::PageMeta;
+use parser::{Doc, ParseConfig};
+use redirect::{load_redirects, save_redirects, Redirect};
+use title::{load_titles, save_titles, Interwiki, Namespace, Title, TitleCheckpoint};
+use update::CrawlState;
 
 /// データ関連のパスを管理する
 ///
@@ -15,28 +21,62 @@ use title::{load_titles, save_titles, Title};
 pub struct Data {
     pub base_dir: PathBuf,
     pub title_file: PathBuf,
+    pub title_checkpoint_file: PathBuf,
+    pub redirect_file: PathBuf,
+    pub namespace_file: PathBuf,
+    pub interwiki_file: PathBuf,
+    pub red_link_file: PathBuf,
+    pub crawl_state_file: PathBuf,
+    pub failure_file: PathBuf,
+    pub parse_config_file: PathBuf,
+    pub link_graph_file: PathBuf,
+    pub triple_file: PathBuf,
     pub markuped_text_dir: PathBuf,
+    /// Markuped texts whose templates are expanded by the wiki
+    pub expanded_text_dir: PathBuf,
+    pub meta_dir: PathBuf,
     pub parsed_text_dir: PathBuf,
     pub biluo_dir: PathBuf,
     markuped_text_file_extension: &'static str,
+    meta_file_extension: &'static str,
     parsed_text_file_extension: &'static str,
     biluo_file_extension: &'static str,
+    /// Serializes appends to `failure_file` from workers
+    failure_lock: Mutex<()>,
 }
 
 impl Data {
     pub fn new(base_dir: &str) -> Data {
         let base_dir = Path::new(base_dir);
         let title_file = base_dir.join("titles.csv");
+        let title_checkpoint_file = base_dir.join("titles_checkpoint.json");
+        let redirect_file = base_dir.join("redirects.csv");
+        let namespace_file = base_dir.join("namespaces.json");
+        let interwiki_file = base_dir.join("interwikis.json");
+        let red_link_file = base_dir.join("red_links.csv");
+        let crawl_state_file = base_dir.join("crawl_state.json");
+        let failure_file = base_dir.join("failures.csv");
+        let parse_config_file = base_dir.join("parse_config.json");
+        let link_graph_file = base_dir.join("links.csv");
+        let triple_file = base_dir.join("graph.nt");
         let markuped_text_dir = base_dir.join("raw");
+        let expanded_text_dir = base_dir.join("expanded");
+        let meta_dir = base_dir.join("meta");
         let parsed_text_dir = base_dir.join("parsed");
         let biluo_dir = base_dir.join("biluo");
 
         if !base_dir.exists() {
-            fs::create_dir(&base_dir).unwrap();
+            fs::create_dir(base_dir).unwrap();
         }
         if !markuped_text_dir.exists() {
             fs::create_dir(&markuped_text_dir).unwrap();
         }
+        if !expanded_text_dir.exists() {
+            fs::create_dir(&expanded_text_dir).unwrap();
+        }
+        if !meta_dir.exists() {
+            fs::create_dir(&meta_dir).unwrap();
+        }
         if !parsed_text_dir.exists() {
             fs::create_dir(&parsed_text_dir).unwrap();
         }
@@ -47,12 +87,26 @@ impl Data {
         Data {
             base_dir: base_dir.to_path_buf(),
             title_file,
+            title_checkpoint_file,
+            redirect_file,
+            namespace_file,
+            interwiki_file,
+            red_link_file,
+            crawl_state_file,
+            failure_file,
+            parse_config_file,
+            link_graph_file,
+            triple_file,
             markuped_text_dir,
+            expanded_text_dir,
+            meta_dir,
             parsed_text_dir,
             biluo_dir,
             markuped_text_file_extension: "txt",
+            meta_file_extension: "json",
             parsed_text_file_extension: "json",
             biluo_file_extension: "tsv",
+            failure_lock: Mutex::new(()),
         }
     }
 
@@ -65,7 +119,7 @@ impl Data {
         glob(&pattern[..])
     }
 
-    fn parsed_text_files(&self) -> Result<Paths, PatternError> {
+    pub fn parsed_text_files(&self) -> Result<Paths, PatternError> {
         let pattern = format!(
             "{}/*.{}",
             self.parsed_text_dir.to_str().unwrap(),
@@ -74,7 +128,7 @@ impl Data {
         glob(&pattern[..])
     }
 
-    fn biluo_files(&self) -> Result<Paths, PatternError> {
+    pub fn biluo_files(&self) -> Result<Paths, PatternError> {
         let pattern = format!(
             "{}/*.{}",
             self.biluo_dir.to_str().unwrap(),
@@ -104,10 +158,142 @@ impl Data {
         load_titles(&self.title_file)
     }
 
+    /// Save titles, removing the checkpoint of `download_titles` which no longer matches them
+    ///
+    /// Save a new checkpoint after this to keep one.
     pub fn save_titles(&self, titles: &Vec<Title>) -> Result<(), Error> {
+        self.remove_title_checkpoint()?;
         save_titles(titles, &self.title_file)
     }
 
+    /// Return None if titles were saved without a checkpoint
+    pub fn load_title_checkpoint(&self) -> Result<Option<TitleCheckpoint>, Error> {
+        if !self.title_checkpoint_file.exists() {
+            return Ok(None);
+        }
+        let f = fs::File::open(&self.title_checkpoint_file)?;
+        Ok(Some(serde_json::from_reader(f)?))
+    }
+
+    pub fn save_title_checkpoint(&self, checkpoint: &TitleCheckpoint) -> Result<(), Error> {
+        let mut f = fs::File::create(&self.title_checkpoint_file)?;
+        f.write_all(serde_json::to_string(checkpoint)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn remove_title_checkpoint(&self) -> Result<(), Error> {
+        if self.title_checkpoint_file.exists() {
+            fs::remove_file(&self.title_checkpoint_file)?;
+        }
+        Ok(())
+    }
+
+    pub fn load_namespaces(&self) -> Result<Vec<Namespace>, Error> {
+        let f = fs::File::open(&self.namespace_file)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    pub fn save_namespaces(&self, namespaces: &[Namespace]) -> Result<(), Error> {
+        let mut f = fs::File::create(&self.namespace_file)?;
+        f.write_all(serde_json::to_string(namespaces)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load_interwikis(&self) -> Result<Vec<Interwiki>, Error> {
+        let f = fs::File::open(&self.interwiki_file)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    pub fn save_interwikis(&self, interwikis: &[Interwiki]) -> Result<(), Error> {
+        let mut f = fs::File::create(&self.interwiki_file)?;
+        f.write_all(serde_json::to_string(interwikis)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load_redirects(&self) -> Result<Vec<Redirect>, Error> {
+        load_redirects(&self.redirect_file)
+    }
+
+    pub fn save_redirects(&self, redirects: &[Redirect]) -> Result<(), Error> {
+        save_redirects(redirects, &self.redirect_file)
+    }
+
+    pub fn save_red_links(&self, red_links: &[RedLink]) -> Result<(), Error> {
+        save_red_links(red_links, &self.red_link_file)
+    }
+
+    /// Return None if no crawl has finished yet
+    pub fn load_crawl_state(&self) -> Result<Option<CrawlState>, Error> {
+        if !self.crawl_state_file.exists() {
+            return Ok(None);
+        }
+        let f = fs::File::open(&self.crawl_state_file)?;
+        Ok(Some(serde_json::from_reader(f)?))
+    }
+
+    pub fn save_crawl_state(&self, state: &CrawlState) -> Result<(), Error> {
+        let mut f = fs::File::create(&self.crawl_state_file)?;
+        f.write_all(serde_json::to_string(state)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Return the default config if the file doesn't exist
+    pub fn load_parse_config(&self) -> Result<ParseConfig, Error> {
+        if !self.parse_config_file.exists() {
+            return Ok(ParseConfig::default());
+        }
+        let f = fs::File::open(&self.parse_config_file)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    /// Append a page which couldn't be fetched to the failures log
+    pub fn save_failure(&self, failure: &Failure) -> Result<(), Error> {
+        let _lock = self.failure_lock.lock().unwrap();
+        let is_new = !self.failure_file.exists();
+        let f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.failure_file)?;
+        let mut wtr = csv::WriterBuilder::new().has_headers(is_new).from_writer(f);
+        wtr.serialize(failure)?;
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Rewrite the failures log, removing it if no failure is left
+    pub fn save_failures(&self, failures: &[Failure]) -> Result<(), Error> {
+        let _lock = self.failure_lock.lock().unwrap();
+        if failures.is_empty() {
+            if self.failure_file.exists() {
+                fs::remove_file(&self.failure_file)?;
+            }
+            return Ok(());
+        }
+        let mut wtr = csv::Writer::from_path(&self.failure_file)?;
+        for failure in failures {
+            wtr.serialize(failure)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    pub fn load_failures(&self) -> Result<Vec<Failure>, Error> {
+        if !self.failure_file.exists() {
+            return Ok(Vec::new());
+        }
+        let mut rdr = csv::Reader::from_path(&self.failure_file)?;
+        let mut failures = Vec::new();
+        for result in rdr.deserialize() {
+            failures.push(result?);
+        }
+        Ok(failures)
+    }
+
+    pub fn load_markuped_text(&self, pageid: u32) -> std::io::Result<String> {
+        let filename = format!("{}.{}", pageid, self.markuped_text_file_extension);
+        fs::read_to_string(self.markuped_text_dir.join(&filename[..]))
+    }
+
     pub fn save_markuped_text(&self, pageid: u32, text: &str) -> std::io::Result<()> {
         let filename = format!("{}.{}", pageid, self.markuped_text_file_extension);
         let filepath = self.markuped_text_dir.join(&filename[..]);
@@ -116,6 +302,53 @@ impl Data {
         Ok(())
     }
 
+    fn expanded_text_path(&self, pageid: u32) -> PathBuf {
+        let filename = format!("{}.{}", pageid, self.markuped_text_file_extension);
+        self.expanded_text_dir.join(&filename[..])
+    }
+
+    pub fn has_expanded_text(&self, pageid: u32) -> bool {
+        self.expanded_text_path(pageid).exists()
+    }
+
+    pub fn load_expanded_text(&self, pageid: u32) -> std::io::Result<String> {
+        fs::read_to_string(self.expanded_text_path(pageid))
+    }
+
+    pub fn save_expanded_text(&self, pageid: u32, text: &str) -> std::io::Result<()> {
+        let mut f = fs::File::create(self.expanded_text_path(pageid))?;
+        f.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Remove the expanded text which became stale since the markuped text was updated
+    pub fn remove_expanded_text(&self, pageid: u32) -> std::io::Result<()> {
+        let filepath = self.expanded_text_path(pageid);
+        if filepath.exists() {
+            fs::remove_file(filepath)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_page_meta(&self, pageid: u32, meta: &PageMeta) -> Result<(), Error> {
+        let filename = format!("{}.{}", pageid, self.meta_file_extension);
+        let filepath = self.meta_dir.join(&filename[..]);
+        let mut f = fs::File::create(filepath)?;
+        f.write_all(serde_json::to_string(meta)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Return None if no metadata was recorded for the page
+    pub fn load_page_meta(&self, pageid: u32) -> Result<Option<PageMeta>, Error> {
+        let filename = format!("{}.{}", pageid, self.meta_file_extension);
+        let filepath = self.meta_dir.join(&filename[..]);
+        if !filepath.exists() {
+            return Ok(None);
+        }
+        let f = fs::File::open(filepath)?;
+        Ok(Some(serde_json::from_reader(f)?))
+    }
+
     pub fn save_parsed_text(&self, pageid: u32, doc: &Doc) -> Result<(), Error> {
         let filename = format!("{}.{}", pageid, self.parsed_text_file_extension);
         let filepath = self.parsed_text_dir.join(&filename[..]);
@@ -123,6 +356,39 @@ impl Data {
         f.write_all(serde_json::to_string(doc)?.as_bytes())?;
         Ok(())
     }
+
+    pub fn load_parsed_text(&self, pageid: u32) -> Result<Doc, Error> {
+        let filename = format!("{}.{}", pageid, self.parsed_text_file_extension);
+        let filepath = self.parsed_text_dir.join(&filename[..]);
+        let f = fs::File::open(filepath)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    pub fn save_biluo(&self, pageid: u32, tsv: &str) -> std::io::Result<()> {
+        let filename = format!("{}.{}", pageid, self.biluo_file_extension);
+        let filepath = self.biluo_dir.join(&filename[..]);
+        let mut f = fs::File::create(filepath)?;
+        f.write_all(tsv.as_bytes())?;
+        Ok(())
+    }
+
+    /// Remove every file of the page
+    pub fn remove_page(&self, pageid: u32) -> std::io::Result<()> {
+        let filepaths = [
+            (&self.markuped_text_dir, self.markuped_text_file_extension),
+            (&self.expanded_text_dir, self.markuped_text_file_extension),
+            (&self.meta_dir, self.meta_file_extension),
+            (&self.parsed_text_dir, self.parsed_text_file_extension),
+            (&self.biluo_dir, self.biluo_file_extension),
+        ];
+        for (dir, extension) in filepaths.iter() {
+            let filepath = dir.join(format!("{}.{}", pageid, extension));
+            if filepath.exists() {
+                fs::remove_file(filepath)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 pub fn parse_pageid(path: &Path) -> u32 {
@@ -154,6 +420,57 @@ mod tests {
         assert_eq!(ids.len(), 1);
         assert!(ids.contains(&893));
 
+        assert!(data.load_page_meta(893)?.is_none());
+        let meta = PageMeta {
+            revid: Some(114514),
+            ..PageMeta::fetched_now()
+        };
+        data.save_page_meta(893, &meta)?;
+        assert_eq!(data.load_page_meta(893)?, Some(meta));
+
+        data.save_expanded_text(893, "Expanded")?;
+        assert_eq!(data.load_expanded_text(893)?, "Expanded");
+        data.remove_page(893)?;
+        assert!(data.make_pageid_set_from_markuped_text_files()?.is_empty());
+        assert!(!data.has_expanded_text(893));
+        assert!(data.load_page_meta(893)?.is_none());
+
+        assert!(data.load_title_checkpoint()?.is_none());
+        let checkpoint = TitleCheckpoint {
+            count: 500,
+            last_title: Some("Ore".to_string()),
+            ns: 0,
+            continuation: Some(
+                vec![("apcontinue".to_string(), "Ore".to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+            completed: false,
+        };
+        data.save_title_checkpoint(&checkpoint)?;
+        assert_eq!(data.load_title_checkpoint()?, Some(checkpoint));
+        data.save_titles(&Vec::new())?;
+        assert!(data.load_title_checkpoint()?.is_none());
+
+        assert!(data.load_crawl_state()?.is_none());
+        let state = CrawlState {
+            last_crawled_at: "2018-11-01T00:00:00Z".to_string(),
+        };
+        data.save_crawl_state(&state)?;
+        assert_eq!(data.load_crawl_state()?, Some(state));
+
+        assert!(data.load_failures()?.is_empty());
+        let failure = Failure::new(893, &format_err!("disk full"));
+        data.save_failure(&failure)?;
+        data.save_failure(&failure)?;
+        let failures = data.load_failures()?;
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[1], failure);
+        data.save_failures(&failures[1..])?;
+        assert_eq!(data.load_failures()?.len(), 1);
+        data.save_failures(&[])?;
+        assert!(!data.failure_file.exists());
+
         fs::remove_dir_all(data.base_dir)?;
         Ok(())
     }
@@ -0,0 +1,369 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use bzip2::read::MultiBzDecoder;
+use failure::Error;
+use indicatif::ProgressBar;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use data::Data;
+use meta::{base36_to_hex, now_timestamp, PageMeta};
+use title::{select_namespaces, Namespace, Page, Title};
+use update::CrawlState;
+
+/// Fields of `<page>` collected until its end tag
+#[derive(Default)]
+struct PageBuilder {
+    id: Option<u32>,
+    ns: Option<i32>,
+    title: Option<String>,
+    text: Option<String>,
+    // of the last revision
+    meta: Option<PageMeta>,
+}
+
+impl PageBuilder {
+    fn build(self, namespaces: &[Namespace]) -> Result<Page, Error> {
+        let ns = self.ns.ok_or_else(|| format_err!("page without <ns>"))?;
+        let ns_name = namespaces
+            .iter()
+            .find(|n| n.id == ns)
+            .map_or_else(String::new, |n| n.name.clone());
+        Ok(Page {
+            title: Title {
+                id: self.id.ok_or_else(|| format_err!("page without <id>"))?,
+                ns,
+                ns_name,
+                name: self
+                    .title
+                    .ok_or_else(|| format_err!("page without <title>"))?,
+            },
+            // pages whose text is deleted have <text deleted="deleted" />
+            text: self.text.unwrap_or_default(),
+            meta: self.meta,
+        })
+    }
+}
+
+/// Return the value of `bytes` attribute of `<text>`
+fn parse_text_size<B: BufRead>(e: &BytesStart, reader: &Reader<B>) -> Result<Option<u32>, Error> {
+    for attr in e.attributes() {
+        let attr = attr?;
+        if attr.key == b"bytes" {
+            return Ok(Some(attr.unescape_and_decode_value(reader)?.parse()?));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_namespace<B: BufRead>(e: &BytesStart, reader: &Reader<B>) -> Result<Namespace, Error> {
+    let mut id = None;
+    let mut case = String::new();
+    for attr in e.attributes() {
+        let attr = attr?;
+        match attr.key {
+            b"key" => id = Some(attr.unescape_and_decode_value(reader)?.parse()?),
+            b"case" => case = attr.unescape_and_decode_value(reader)?,
+            _ => (),
+        }
+    }
+    Ok(Namespace {
+        id: id.ok_or_else(|| format_err!("namespace without key"))?,
+        case,
+        // dumps only have local names
+        canonical: None,
+        name: String::new(),
+    })
+}
+
+/// Stream pages in a MediaWiki XML dump, calling `on_page` with each page and the
+/// namespace table in `<siteinfo>`
+///
+/// Only the last revision of each page is kept, so history dumps work as well.
+/// Return the namespace table.
+///
+/// see: https://www.mediawiki.org/wiki/Help:Export#Export_format
+pub fn read_dump<R, F>(reader: R, mut on_page: F) -> Result<Vec<Namespace>, Error>
+where
+    R: BufRead,
+    F: FnMut(&[Namespace], Page) -> Result<(), Error>,
+{
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    // names of open elements
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    let mut text = String::new();
+    let mut namespaces = Vec::new();
+    let mut page = PageBuilder::default();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(ref e) => {
+                match e.name() {
+                    b"namespace" => namespaces.push(parse_namespace(e, &reader)?),
+                    b"page" => page = PageBuilder::default(),
+                    b"revision" => {
+                        page.meta = Some(PageMeta {
+                            fetched_at: now_timestamp(),
+                            ..PageMeta::default()
+                        })
+                    }
+                    b"text" => {
+                        if let Some(ref mut meta) = page.meta {
+                            meta.size = parse_text_size(e, &reader)?;
+                        }
+                    }
+                    _ => (),
+                }
+                path.push(e.name().to_vec());
+                text.clear();
+            }
+            Event::Empty(ref e) => match e.name() {
+                b"namespace" => namespaces.push(parse_namespace(e, &reader)?),
+                b"text" => {
+                    page.text = Some(String::new());
+                    if let Some(ref mut meta) = page.meta {
+                        meta.size = parse_text_size(e, &reader)?;
+                    }
+                }
+                _ => (),
+            },
+            Event::Text(ref e) => text.push_str(&e.unescape_and_decode(&reader)?),
+            Event::CData(ref e) => text.push_str(reader.decode(e)?),
+            Event::End(ref e) => {
+                let parent = if path.len() >= 2 {
+                    &path[path.len() - 2][..]
+                } else {
+                    &b""[..]
+                };
+                match (parent, e.name()) {
+                    (b"namespaces", b"namespace") => {
+                        if let Some(ns) = namespaces.last_mut() {
+                            ns.name = text.clone();
+                        }
+                    }
+                    (b"page", b"id") => page.id = Some(text.trim().parse()?),
+                    (b"page", b"ns") => page.ns = Some(text.trim().parse()?),
+                    (b"page", b"title") => page.title = Some(text.clone()),
+                    (b"revision", b"text") => page.text = Some(text.clone()),
+                    (b"revision", field) => {
+                        if let Some(ref mut meta) = page.meta {
+                            match field {
+                                b"id" => meta.revid = Some(text.trim().parse()?),
+                                b"timestamp" => meta.timestamp = Some(text.trim().to_string()),
+                                b"model" => meta.content_model = Some(text.trim().to_string()),
+                                b"sha1" => meta.sha1 = base36_to_hex(text.trim()),
+                                _ => (),
+                            }
+                        }
+                    }
+                    (_, b"page") => {
+                        let built = std::mem::take(&mut page).build(&namespaces)?;
+                        on_page(&namespaces, built)?;
+                    }
+                    _ => (),
+                }
+                path.pop();
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(namespaces)
+}
+
+/// Open a dump decompressing it if its name ends with ".bz2"
+fn open_dump(filepath: &Path) -> Result<Box<dyn BufRead>, Error> {
+    let f = File::open(filepath)?;
+    if filepath.extension().is_some_and(|e| e == "bz2") {
+        // Wikimedia publishes multistream dumps concatenating bzip2 streams
+        Ok(Box::new(BufReader::new(MultiBzDecoder::new(f))))
+    } else {
+        Ok(Box::new(BufReader::new(f)))
+    }
+}
+
+/// Fill titles and markuped texts of `data_dir` from a dump, only with pages in namespaces
+/// designated by `specs`
+pub fn import_dump(dump_file: &str, data_dir: &str, specs: &[&str]) -> Result<(), Error> {
+    let data = Data::new(data_dir);
+    let mut titles = Vec::new();
+    let mut selected: Option<HashSet<i32>> = None;
+    // changes after the latest revision in the dump are retrieved by `update`
+    let mut latest: Option<String> = None;
+    let pb = ProgressBar::new_spinner();
+    let namespaces = read_dump(open_dump(Path::new(dump_file))?, |namespaces, page| {
+        if selected.is_none() {
+            let ids = select_namespaces(namespaces, specs)?
+                .iter()
+                .map(|n| n.id)
+                .collect();
+            selected = Some(ids);
+        }
+        if selected
+            .as_ref()
+            .is_some_and(|ids| ids.contains(&page.title.ns))
+        {
+            data.save_markuped_text(page.title.id, &page.text[..])?;
+            if let Some(ref meta) = page.meta {
+                data.save_page_meta(page.title.id, meta)?;
+                if meta.timestamp > latest {
+                    latest = meta.timestamp.clone();
+                }
+            }
+            titles.push(page.title);
+        }
+        pb.inc(1);
+        Ok(())
+    })?;
+    pb.finish_with_message("done");
+    data.save_namespaces(&namespaces)?;
+    if let Some(last_crawled_at) = latest {
+        data.save_crawl_state(&CrawlState { last_crawled_at })?;
+    }
+    data.save_titles(&titles)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Cursor, Read, Write};
+
+    use bzip2::write::BzEncoder;
+    use bzip2::Compression;
+
+    use super::*;
+
+    const DUMP: &str = r#"<mediawiki xmlns="http://www.mediawiki.org/xml/export-0.10/" version="0.10" xml:lang="en">
+  <siteinfo>
+    <sitename>Minecraft Wiki</sitename>
+    <namespaces>
+      <namespace key="0" case="first-letter" />
+      <namespace key="10" case="first-letter">Template</namespace>
+      <namespace key="14" case="first-letter">Category</namespace>
+    </namespaces>
+  </siteinfo>
+  <page>
+    <title>Ore</title>
+    <ns>0</ns>
+    <id>3020</id>
+    <revision>
+      <id>1</id>
+      <timestamp>2018-10-01T00:00:00Z</timestamp>
+      <contributor><username>Alice</username><id>42</id></contributor>
+      <model>wikitext</model>
+      <format>text/x-wiki</format>
+      <text xml:space="preserve" bytes="10">Old &amp; rev</text>
+      <sha1>abc</sha1>
+    </revision>
+    <revision>
+      <id>2</id>
+      <timestamp>2018-11-01T00:00:00Z</timestamp>
+      <contributor><username>Bob</username><id>43</id></contributor>
+      <model>wikitext</model>
+      <format>text/x-wiki</format>
+      <text xml:space="preserve" bytes="33">'''Ores''' are [[rare]] &lt;blocks&gt;.</text>
+      <sha1>2tmen9rvo63kc55009zkmto32tt6otv</sha1>
+    </revision>
+  </page>
+  <page>
+    <title>Category:Ore</title>
+    <ns>14</ns>
+    <id>5000</id>
+    <revision>
+      <id>3</id>
+      <text xml:space="preserve" bytes="0" />
+    </revision>
+  </page>
+  <page>
+    <title>Template:BlockLink</title>
+    <ns>10</ns>
+    <id>6000</id>
+    <revision>
+      <id>4</id>
+      <text xml:space="preserve" bytes="6">&lt;span&gt;</text>
+    </revision>
+  </page>
+</mediawiki>
+"#;
+
+    #[test]
+    fn test_read_dump() -> Result<(), Error> {
+        let mut pages = Vec::new();
+        let namespaces = read_dump(Cursor::new(DUMP), |_, page| {
+            pages.push(page);
+            Ok(())
+        })?;
+        assert_eq!(namespaces.len(), 3);
+        assert_eq!(namespaces[0].id, 0);
+        assert_eq!(namespaces[0].name, "");
+        assert_eq!(namespaces[2].id, 14);
+        assert_eq!(namespaces[2].name, "Category");
+
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].title.id, 3020);
+        assert_eq!(pages[0].title.ns, 0);
+        assert_eq!(pages[0].title.name, "Ore");
+        assert_eq!(pages[0].text, "'''Ores''' are [[rare]] <blocks>.");
+        let meta = pages[0].meta.as_ref().unwrap();
+        assert_eq!(meta.revid, Some(2));
+        assert_eq!(meta.timestamp, Some("2018-11-01T00:00:00Z".to_string()));
+        assert_eq!(meta.content_model, Some("wikitext".to_string()));
+        assert_eq!(meta.size, Some(33));
+        assert_eq!(
+            meta.sha1,
+            Some("182a775b19e25fe5e0403e76052088351a157433".to_string())
+        );
+        assert_eq!(pages[1].meta.as_ref().unwrap().size, Some(0));
+        assert_eq!(pages[1].title.ns_name, "Category");
+        assert_eq!(pages[1].text, "");
+        assert_eq!(pages[2].text, "<span>");
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_dump() -> Result<(), Error> {
+        let data_dir = "test_dir_dump_364364";
+        let data = Data::new(data_dir);
+        let dump_file = data.base_dir.join("dump.xml");
+        fs::write(&dump_file, DUMP)?;
+        let res = import_dump(dump_file.to_str().unwrap(), data_dir, &["Main", "Category"]);
+        let titles = data.load_titles();
+        let ids = data.make_pageid_set_from_markuped_text_files();
+        let text = fs::read_to_string(data.markuped_text_dir.join("3020.txt"));
+        let meta = data.load_page_meta(3020);
+        let state = data.load_crawl_state();
+        fs::remove_dir_all(&data.base_dir)?;
+
+        res?;
+        let titles = titles?;
+        assert_eq!(titles.len(), 2);
+        assert_eq!(titles[1].name, "Category:Ore");
+        assert_eq!(titles[1].ns_name, "Category");
+        let ids = ids?;
+        assert_eq!(ids.len(), 2);
+        assert!(!ids.contains(&6000));
+        assert_eq!(text?, "'''Ores''' are [[rare]] <blocks>.");
+        assert_eq!(meta?.unwrap().revid, Some(2));
+        assert_eq!(state?.unwrap().last_crawled_at, "2018-11-01T00:00:00Z");
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_dump_bz2() -> Result<(), Error> {
+        let dump_file = Path::new("tmp_dump_364364.xml.bz2");
+        let mut encoder = BzEncoder::new(File::create(dump_file)?, Compression::default());
+        encoder.write_all(DUMP.as_bytes())?;
+        encoder.finish()?;
+        let mut decoded = String::new();
+        let res = open_dump(dump_file).and_then(|mut r| Ok(r.read_to_string(&mut decoded)?));
+        fs::remove_file(dump_file)?;
+        res?;
+        assert_eq!(decoded, DUMP);
+        Ok(())
+    }
+}
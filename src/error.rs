@@ -0,0 +1,131 @@
+use std::fmt;
+
+use failure::{Error, Fail};
+
+use meta::now_timestamp;
+
+/// Error of a request to a wiki
+#[derive(Debug)]
+pub enum FetchError {
+    Network(String),
+    /// `retry_after` is seconds told by the Retry-After header
+    HttpStatus {
+        status: u16,
+        retry_after: Option<u64>,
+    },
+    Api {
+        code: String,
+        info: String,
+    },
+    /// The database lagged more than `maxlag` by the seconds
+    Maxlag(u64),
+    MissingPage(String),
+    Parse(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FetchError::Network(e) => write!(f, "network error: {}", e),
+            FetchError::HttpStatus { status, .. } => write!(f, "HTTP status {}", status),
+            FetchError::Api { code, info } => write!(f, "API error {}: {}", code, info),
+            FetchError::Maxlag(secs) => write!(f, "database lagged {} secs", secs),
+            FetchError::MissingPage(title) => write!(f, "page \"{}\" is missing", title),
+            FetchError::Parse(e) => write!(f, "can't parse response: {}", e),
+        }
+    }
+}
+
+impl Fail for FetchError {}
+
+impl FetchError {
+    /// Whether sending the same request again may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FetchError::Network(_) | FetchError::Maxlag(_) => true,
+            FetchError::HttpStatus { status, .. } => *status == 429 || *status >= 500,
+            FetchError::Api { code, .. } => code == "ratelimited" || code == "readonly",
+            FetchError::MissingPage(_) | FetchError::Parse(_) => false,
+        }
+    }
+
+    /// Seconds the server asked to wait before retrying
+    pub fn wait_secs(&self) -> Option<u64> {
+        match self {
+            FetchError::HttpStatus { retry_after, .. } => *retry_after,
+            FetchError::Maxlag(secs) => Some(*secs),
+            _ => None,
+        }
+    }
+
+    /// Short name of the variant recorded in the failures log
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FetchError::Network(_) => "network",
+            FetchError::HttpStatus { .. } => "http_status",
+            FetchError::Api { .. } => "api",
+            FetchError::Maxlag(_) => "maxlag",
+            FetchError::MissingPage(_) => "missing_page",
+            FetchError::Parse(_) => "parse",
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> FetchError {
+        FetchError::Network(e.to_string())
+    }
+}
+
+/// A page which couldn't be fetched even after retries
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Failure {
+    pub pageid: u32,
+    /// `FetchError::kind` or "other"
+    pub kind: String,
+    pub message: String,
+    pub failed_at: String,
+}
+
+impl Failure {
+    pub fn new(pageid: u32, e: &Error) -> Failure {
+        Failure {
+            pageid,
+            kind: e
+                .downcast_ref::<FetchError>()
+                .map_or("other", FetchError::kind)
+                .to_string(),
+            message: e.to_string(),
+            failed_at: now_timestamp(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_retryable() {
+        let status = |status| FetchError::HttpStatus {
+            status,
+            retry_after: None,
+        };
+        assert!(FetchError::Network("timed out".to_string()).is_retryable());
+        assert!(FetchError::Maxlag(3).is_retryable());
+        assert!(status(503).is_retryable());
+        assert!(status(429).is_retryable());
+        assert!(!status(404).is_retryable());
+        assert!(!status(403).is_retryable());
+        assert!(!FetchError::MissingPage("Ore".to_string()).is_retryable());
+    }
+
+    #[test]
+    fn test_failure() {
+        let failure = Failure::new(3020, &FetchError::MissingPage("Ore".to_string()).into());
+        assert_eq!(failure.kind, "missing_page");
+        assert_eq!(failure.message, "page \"Ore\" is missing");
+        let failure = Failure::new(3020, &format_err!("disk full"));
+        assert_eq!(failure.kind, "other");
+    }
+}
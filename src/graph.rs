@@ -0,0 +1,348 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+use failure::Error;
+use regex::Regex;
+use url::percent_encoding::{utf8_percent_encode, EncodeSet, PATH_SEGMENT_ENCODE_SET};
+
+use data::{parse_pageid, Data};
+use link::Linker;
+use parser::{Doc, Entity};
+
+const RDFS_LABEL: &str = "<http://www.w3.org/2000/01/rdf-schema#label>";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+/// A link from a page to another
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LinkEdge {
+    pub source_id: u32,
+    pub target: String,
+    /// None for red links or if entities aren't linked
+    pub target_id: Option<u32>,
+    pub anchor_text: String,
+    /// Innermost section of the link or its table, None before the first heading
+    pub section: Option<String>,
+}
+
+/// Return the heading of the innermost section containing `offset`
+fn section_at(doc: &Doc, offset: u32) -> Option<String> {
+    doc.sections
+        .iter()
+        .rfind(|s| s.start <= offset && offset < s.end)
+        .map(|s| s.heading.clone())
+}
+
+/// Return links in the text and tables of a doc
+pub fn link_edges(pageid: u32, doc: &Doc) -> Vec<LinkEdge> {
+    let edge = |entity: &Entity, section| LinkEdge {
+        source_id: pageid,
+        target: entity.target.clone(),
+        target_id: entity.target_id,
+        anchor_text: entity.repr.clone(),
+        section,
+    };
+    let mut edges: Vec<LinkEdge> = doc
+        .entities
+        .iter()
+        .map(|e| edge(e, section_at(doc, e.start)))
+        .collect();
+    for table in &doc.tables {
+        for cell in table.rows.iter().flatten() {
+            edges.extend(cell.entities.iter().map(|e| edge(e, table.section.clone())));
+        }
+    }
+    edges
+}
+
+/// IRIs of pages and predicates under a base IRI, e.g. `<urn:mwkb:page/42>`
+pub struct Vocabulary {
+    base: String,
+}
+
+/// Path segment encode set plus the rest of the characters N-Triples forbids in IRIs
+#[derive(Clone)]
+struct IriSegmentEncodeSet;
+
+impl EncodeSet for IriSegmentEncodeSet {
+    fn contains(&self, byte: u8) -> bool {
+        PATH_SEGMENT_ENCODE_SET.contains(byte) || is_forbidden_in_iri(byte as char)
+    }
+}
+
+/// Whether N-Triples forbids the character in IRIs
+fn is_forbidden_in_iri(c: char) -> bool {
+    c <= ' ' || "<>\"{}|^`\\".contains(c)
+}
+
+/// Percent-encode a title or name as a path segment, putting `_` for spaces as MediaWiki does
+fn encode(name: &str) -> String {
+    utf8_percent_encode(&name.replace(' ', "_"), IriSegmentEncodeSet).to_string()
+}
+
+impl Vocabulary {
+    /// Fail if `base` isn't an IRI with a scheme or has characters forbidden in N-Triples
+    pub fn new(base: &str) -> Result<Vocabulary, Error> {
+        lazy_static! {
+            static ref SCHEME_RE: Regex = Regex::new(r"^[A-Za-z][A-Za-z0-9+.-]*:").unwrap();
+        }
+        if !SCHEME_RE.is_match(base) {
+            return Err(format_err!(
+                "the base IRI {:?} doesn't start with a scheme, e.g. \"urn:\"",
+                base
+            ));
+        }
+        if let Some(c) = base.chars().find(|&c| is_forbidden_in_iri(c)) {
+            return Err(format_err!(
+                "the base IRI {:?} has {:?}, which isn't allowed in IRIs",
+                base,
+                c
+            ));
+        }
+        Ok(Vocabulary {
+            base: base.to_string(),
+        })
+    }
+
+    pub fn page(&self, pageid: u32) -> String {
+        format!("<{}page/{}>", self.base, pageid)
+    }
+
+    /// Pages are identified by their ids if known, otherwise by their titles
+    pub fn target(&self, target: &str, target_id: Option<u32>) -> String {
+        match target_id {
+            Some(id) => self.page(id),
+            None => format!("<{}title/{}>", self.base, encode(target)),
+        }
+    }
+
+    pub fn links_to(&self) -> String {
+        format!("<{}ontology/linksTo>", self.base)
+    }
+
+    pub fn property(&self, name: &str) -> String {
+        format!("<{}property/{}>", self.base, encode(name))
+    }
+}
+
+/// Return an N-Triples string literal of the text
+fn plain_literal(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\"{}\"", escaped)
+}
+
+/// Return an N-Triples literal, typed as integer, decimal or boolean if the text looks so
+fn typed_literal(text: &str) -> String {
+    lazy_static! {
+        static ref INTEGER_RE: Regex = Regex::new(r"^[-+]?\d+$").unwrap();
+        static ref DECIMAL_RE: Regex = Regex::new(r"^[-+]?\d*\.\d+$").unwrap();
+    }
+    let literal = plain_literal(text);
+    let datatype = if INTEGER_RE.is_match(text) {
+        "integer"
+    } else if DECIMAL_RE.is_match(text) {
+        "decimal"
+    } else {
+        match &text.to_lowercase()[..] {
+            "true" | "yes" => return format!("\"true\"^^<{}boolean>", XSD),
+            "false" | "no" => return format!("\"false\"^^<{}boolean>", XSD),
+            _ => return literal,
+        }
+    };
+    format!("{}^^<{}{}>", literal, XSD, datatype)
+}
+
+/// Return N-Triples of a page: its title as a string, links and infobox properties
+///
+/// A property whose value has links points the linked pages, otherwise it has a typed literal.
+/// Duplicate links are written once.
+pub fn to_ntriples(pageid: u32, title: Option<&str>, doc: &Doc, vocab: &Vocabulary) -> Vec<String> {
+    let subject = vocab.page(pageid);
+    let mut triples = Vec::new();
+    if let Some(title) = title {
+        triples.push(format!(
+            "{} {} {} .",
+            subject,
+            RDFS_LABEL,
+            plain_literal(title)
+        ));
+    }
+    let targets: BTreeSet<String> = link_edges(pageid, doc)
+        .iter()
+        .filter(|e| !e.target.is_empty())
+        .map(|e| vocab.target(&e.target, e.target_id))
+        .collect();
+    for target in targets {
+        triples.push(format!("{} {} {} .", subject, vocab.links_to(), target));
+    }
+    for (name, property) in &doc.properties {
+        let predicate = vocab.property(name);
+        let objects: Vec<String> = if property.entities.is_empty() {
+            vec![typed_literal(&property.text)]
+        } else {
+            property
+                .entities
+                .iter()
+                .filter(|e| !e.target.is_empty())
+                .map(|e| vocab.target(&e.target, e.target_id))
+                .collect()
+        };
+        for object in objects {
+            triples.push(format!("{} {} {} .", subject, predicate, object));
+        }
+    }
+    triples
+}
+
+/// Write the link graph of parsed docs to `links.csv` and it with infobox facts to `graph.nt`
+///
+/// Entities are linked beforehand if `titles.csv` exists.
+pub fn export_graph(data_dir: &str, base_iri: &str) -> Result<(), Error> {
+    let data = Data::new(data_dir);
+    let (linker, titles) = if data.title_file.exists() {
+        let titles: HashMap<u32, String> = data
+            .load_titles()?
+            .into_iter()
+            .map(|t| (t.id, t.name))
+            .collect();
+        (Some(Linker::from_data(&data)?), titles)
+    } else {
+        (None, HashMap::new())
+    };
+    let vocab = Vocabulary::new(base_iri)?;
+    let mut pageids = Vec::new();
+    for entry in data.parsed_text_files()? {
+        match entry {
+            Ok(path) => pageids.push(parse_pageid(&path)),
+            Err(e) => {
+                eprintln!("{:?}", e);
+            }
+        }
+    }
+    pageids.sort();
+    let mut edges = csv::Writer::from_path(&data.link_graph_file)?;
+    let mut triples = BufWriter::new(fs::File::create(&data.triple_file)?);
+    for pageid in pageids {
+        let mut doc = data.load_parsed_text(pageid)?;
+        if let Some(ref linker) = linker {
+            linker.link_entities(pageid, &mut doc);
+        }
+        for edge in link_edges(pageid, &doc) {
+            edges.serialize(edge)?;
+        }
+        let title = titles.get(&pageid).map(String::as_str);
+        for triple in to_ntriples(pageid, title, &doc, &vocab) {
+            writeln!(triples, "{}", triple)?;
+        }
+    }
+    edges.flush()?;
+    triples.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_edges() -> Result<(), Error> {
+        let doc = Doc::parse(
+            "Found in the [[Overworld]].
+== Usage ==
+Smelt into [[Iron Ingot|ingots]].
+=== Crafting ===
+Craft [[Iron Ingot|ingots]] into [[tools]].
+{|
+| [[Anvil]]
+|}",
+        )?;
+        let edges = link_edges(3020, &doc);
+        let summary: Vec<(&str, &str, Option<&str>)> = edges
+            .iter()
+            .map(|e| (&e.target[..], &e.anchor_text[..], e.section.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Overworld", "Overworld", None),
+                ("Iron Ingot", "ingots", Some("Usage")),
+                ("Iron Ingot", "ingots", Some("Crafting")),
+                ("Tools", "tools", Some("Crafting")),
+                ("Anvil", "Anvil", Some("Crafting")),
+            ]
+        );
+        assert!(edges.iter().all(|e| e.source_id == 3020));
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_literal() {
+        assert_eq!(typed_literal("64"), format!("\"64\"^^<{}integer>", XSD));
+        assert_eq!(typed_literal("0.5"), format!("\"0.5\"^^<{}decimal>", XSD));
+        assert_eq!(typed_literal("No"), format!("\"false\"^^<{}boolean>", XSD));
+        assert_eq!(typed_literal("Say \"hi\"\n"), "\"Say \\\"hi\\\"\\n\"");
+    }
+
+    #[test]
+    fn test_to_ntriples() -> Result<(), Error> {
+        let mut doc = Doc::parse(
+            "{{Block|tool=[[Pickaxe]]|stackable=64}}
+[[Iron Ore]] drops [[raw iron]] and [[raw iron]].",
+        )?;
+        doc.entities[0].target_id = Some(7315);
+        let vocab = Vocabulary::new("urn:mwkb:")?;
+        let triples = to_ntriples(3020, Some("Iron Ore"), &doc, &vocab);
+        assert_eq!(
+            triples,
+            vec![
+                "<urn:mwkb:page/3020> <http://www.w3.org/2000/01/rdf-schema#label> \"Iron Ore\" ."
+                    .to_string(),
+                "<urn:mwkb:page/3020> <urn:mwkb:ontology/linksTo> <urn:mwkb:page/7315> ."
+                    .to_string(),
+                "<urn:mwkb:page/3020> <urn:mwkb:ontology/linksTo> <urn:mwkb:title/Raw_iron> ."
+                    .to_string(),
+                format!(
+                    "<urn:mwkb:page/3020> <urn:mwkb:property/stackable> \"64\"^^<{}integer> .",
+                    XSD
+                ),
+                "<urn:mwkb:page/3020> <urn:mwkb:property/tool> <urn:mwkb:title/Pickaxe> ."
+                    .to_string(),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_ntriples_label() -> Result<(), Error> {
+        let doc = Doc::parse("")?;
+        let vocab = Vocabulary::new("urn:mwkb:")?;
+        // titles looking like numbers or booleans stay strings
+        for title in &["1000", "Yes"] {
+            assert_eq!(
+                to_ntriples(1, Some(title), &doc, &vocab),
+                vec![format!("<urn:mwkb:page/1> {} \"{}\" .", RDFS_LABEL, title)]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_vocabulary() -> Result<(), Error> {
+        let vocab = Vocabulary::new("https://example.com/kb/")?;
+        assert_eq!(
+            vocab.target("A|B^C\\D{E}", None),
+            "<https://example.com/kb/title/A%7CB%5EC%5CD%7BE%7D>"
+        );
+        assert_eq!(vocab.property("a b"), "<https://example.com/kb/property/a_b>");
+        assert!(Vocabulary::new("").is_err());
+        assert!(Vocabulary::new("kb/").is_err());
+        assert!(Vocabulary::new("urn:my kb:").is_err());
+        assert!(Vocabulary::new("urn:<kb>:").is_err());
+        Ok(())
+    }
+}
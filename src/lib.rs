@@ -1,3 +1,4 @@
+extern crate bzip2;
 extern crate csv;
 #[macro_use]
 extern crate failure;
@@ -6,13 +7,26 @@ extern crate indicatif;
 #[macro_use]
 extern crate lazy_static;
 extern crate parse_wiki_text;
+extern crate quick_xml;
+extern crate rand;
 extern crate regex;
 extern crate reqwest;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate url;
 
 pub mod api;
+pub mod biluo;
+pub mod client;
+pub mod dump;
+pub mod error;
+pub mod graph;
+pub mod link;
+pub mod meta;
+pub mod normalize;
 pub mod parser;
+pub mod redirect;
 pub mod title;
+pub mod update;
 pub mod data;
@@ -0,0 +1,365 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use failure::Error;
+
+use data::{parse_pageid, Data};
+use parser::{Doc, Entity};
+use redirect::{Redirect, RedirectMap};
+use title::{Interwiki, Namespace, Title};
+
+/// Resolve link targets into canonical titles and their page ids
+pub struct Linker {
+    redirects: RedirectMap,
+    ids: HashMap<String, u32>,
+    /// Namespace table of the wiki
+    namespaces: Vec<Namespace>,
+    /// Namespaces of the titles
+    downloaded_namespaces: HashSet<i32>,
+    /// Lowercased interwiki prefixes of the wiki
+    interwiki_prefixes: HashSet<String>,
+}
+
+impl Linker {
+    pub fn new(
+        titles: &[Title],
+        redirects: Vec<Redirect>,
+        namespaces: Vec<Namespace>,
+        interwikis: &[Interwiki],
+    ) -> Linker {
+        Linker {
+            redirects: RedirectMap::new(redirects),
+            ids: titles.iter().map(|t| (t.name.clone(), t.id)).collect(),
+            namespaces,
+            downloaded_namespaces: titles.iter().map(|t| t.ns).collect(),
+            interwiki_prefixes: interwikis.iter().map(|i| i.prefix.to_lowercase()).collect(),
+        }
+    }
+
+    /// Load titles and, if downloaded, redirects and the namespace and interwiki tables of
+    /// the wiki
+    pub fn from_data(data: &Data) -> Result<Linker, Error> {
+        let redirects = if data.redirect_file.exists() {
+            data.load_redirects()?
+        } else {
+            Vec::new()
+        };
+        let namespaces = if data.namespace_file.exists() {
+            data.load_namespaces()?
+        } else {
+            Vec::new()
+        };
+        let interwikis = if data.interwiki_file.exists() {
+            data.load_interwikis()?
+        } else {
+            Vec::new()
+        };
+        Ok(Linker::new(&data.load_titles()?, redirects, namespaces, &interwikis))
+    }
+
+    /// Return the page id of `title`, or None if no such page exists on the wiki
+    pub fn page_id(&self, title: &str) -> Option<u32> {
+        self.ids.get(title).cloned()
+    }
+
+    /// Whether `target` is an interwiki or interlanguage link (e.g. `ja:鉱石`) or in a
+    /// namespace whose titles weren't downloaded, so that it may exist without a title
+    ///
+    /// Other titles with `:` (e.g. `Minecraft: Story Mode`) are in the main namespace.
+    pub fn is_outside(&self, target: &str) -> bool {
+        let prefix = match target.find(':') {
+            Some(i) => target[..i].trim(),
+            None => return false,
+        };
+        if self.interwiki_prefixes.contains(&prefix.to_lowercase()) {
+            return true;
+        }
+        self.namespaces
+            .iter()
+            .find(|ns| ns.id != 0 && ns.has_name(prefix))
+            .is_some_and(|ns| !self.downloaded_namespaces.contains(&ns.id))
+    }
+
+    /// Rewrite targets of redirect links so that they point canonical pages, and fill
+    /// `target_id` of every entity
+    ///
+    /// A redirect to a section gives its section as `anchor` unless the link has its own.
+    /// `target_id` of links to non-existent pages (red links) is left None.
+    /// Links only to a section of the same page get `pageid` of the doc itself.
+    /// Entities in table cells and properties are linked as well.
+    pub fn link_entities(&self, pageid: u32, doc: &mut Doc) {
+        for entity in &mut doc.entities {
+            self.link_entity(pageid, entity);
+        }
+        for (_, entities) in doc.fragments_mut() {
+            for entity in entities {
+                self.link_entity(pageid, entity);
+            }
+        }
+    }
+
+    fn link_entity(&self, pageid: u32, entity: &mut Entity) {
+        if entity.target.is_empty() {
+            entity.target_id = Some(pageid);
+            return;
+        }
+        let mut redirect_id = None;
+        if let Some(redirect) = self.redirects.resolve(&entity.target) {
+            let from = std::mem::replace(&mut entity.target, redirect.to.clone());
+            entity.redirected_from = Some(from);
+            if entity.anchor.is_none() {
+                entity.anchor = redirect.fragment.clone();
+            }
+            redirect_id = redirect.to_id;
+        }
+        entity.target_id = self.page_id(&entity.target).or(redirect_id);
+    }
+}
+
+pub fn link_all_entities(data_dir: &str) -> Result<(), Error> {
+    let data = Data::new(data_dir);
+    let linker = Linker::from_data(&data)?;
+    for entry in data.parsed_text_files()? {
+        match entry {
+            Ok(path) => {
+                let pageid = parse_pageid(&path);
+                let mut doc = data.load_parsed_text(pageid)?;
+                linker.link_entities(pageid, &mut doc);
+                data.save_parsed_text(pageid, &doc)?;
+            }
+            Err(e) => {
+                eprintln!("{:?}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A link target which doesn't exist on the wiki
+#[derive(Serialize, Debug)]
+pub struct RedLink {
+    pub target: String,
+    /// Number of links to the target
+    pub count: u32,
+    /// Space-separated ids of pages linking to the target
+    pub source_ids: String,
+}
+
+/// Aggregate red links in the text, table cells and properties of docs paired with their
+/// page ids
+///
+/// Targets outside the downloaded titles (see `Linker::is_outside`) aren't counted.
+/// Red links are sorted by descending count.
+pub fn collect_red_links<I>(docs: I, linker: &Linker) -> Vec<RedLink>
+where
+    I: IntoIterator<Item = (u32, Doc)>,
+{
+    let mut counts: HashMap<String, (u32, BTreeSet<u32>)> = HashMap::new();
+    for (pageid, mut doc) in docs {
+        linker.link_entities(pageid, &mut doc);
+        let fragment_entities: Vec<Entity> = doc
+            .fragments_mut()
+            .flat_map(|(_, entities)| entities.drain(..))
+            .collect();
+        for entity in doc.entities.into_iter().chain(fragment_entities) {
+            if entity.target_id.is_none() && !linker.is_outside(&entity.target) {
+                let entry = counts.entry(entity.target).or_default();
+                entry.0 += 1;
+                entry.1.insert(pageid);
+            }
+        }
+    }
+    let mut red_links: Vec<RedLink> = counts
+        .into_iter()
+        .map(|(target, (count, ids))| RedLink {
+            target,
+            count,
+            source_ids: ids
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(" "),
+        }).collect();
+    red_links.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.target.cmp(&b.target)));
+    red_links
+}
+
+pub fn save_red_links<P: AsRef<Path>>(red_links: &[RedLink], filepath: P) -> Result<(), Error> {
+    let mut wtr = csv::Writer::from_path(filepath)?;
+    for red_link in red_links {
+        wtr.serialize(red_link)?;
+    }
+    Ok(())
+}
+
+/// Scan parsed docs and save every link target missing from the wiki
+pub fn report_red_links(data_dir: &str) -> Result<Vec<RedLink>, Error> {
+    let data = Data::new(data_dir);
+    let linker = Linker::from_data(&data)?;
+    // docs are loaded one by one, stopping at the first one which can't be loaded
+    let mut error = None;
+    let docs = data
+        .parsed_text_files()?
+        .filter_map(|entry| match entry {
+            Ok(path) => Some(parse_pageid(&path)),
+            Err(e) => {
+                eprintln!("{:?}", e);
+                None
+            }
+        }).map(|pageid| data.load_parsed_text(pageid).map(|doc| (pageid, doc)))
+        .scan(&mut error, |error, res| match res {
+            Ok(doc) => Some(doc),
+            Err(e) => {
+                **error = Some(e);
+                None
+            }
+        });
+    let red_links = collect_red_links(docs, &linker);
+    if let Some(e) = error {
+        return Err(e);
+    }
+    data.save_red_links(&red_links)?;
+    Ok(red_links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn title(id: u32, name: &str) -> Title {
+        Title {
+            id,
+            ns: 0,
+            ns_name: "".to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    fn namespace(id: i32, canonical: Option<&str>, name: &str) -> Namespace {
+        Namespace {
+            id,
+            case: "first-letter".to_string(),
+            canonical: canonical.map(|c| c.to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    fn sample_namespaces() -> Vec<Namespace> {
+        vec![
+            namespace(0, None, ""),
+            namespace(14, Some("Category"), "Category"),
+            namespace(10000, None, "Tutorial"),
+        ]
+    }
+
+    fn sample_interwikis() -> Vec<Interwiki> {
+        vec![Interwiki {
+            prefix: "ja".to_string(),
+            url: "https://minecraft-ja.gamepedia.com/$1".to_string(),
+        }]
+    }
+
+    fn sample_linker() -> Linker {
+        let titles = vec![title(1024, "Slime"), title(812, "Iron Bar")];
+        let redirects = vec![
+            Redirect {
+                from: "Slimes".to_string(),
+                to: "Slime".to_string(),
+                to_id: Some(1024),
+                fragment: None,
+            },
+            Redirect {
+                from: "Slimeball".to_string(),
+                to: "Slime".to_string(),
+                to_id: Some(1024),
+                fragment: Some("Drops".to_string()),
+            },
+        ];
+        Linker::new(&titles, redirects, sample_namespaces(), &sample_interwikis())
+    }
+
+    #[test]
+    fn test_link_entities() -> Result<(), Error> {
+        let linker = sample_linker();
+        let mut doc = Doc::parse("A bonus drop from [[Slimes]] or [[Slime|slimes]].")?;
+        linker.link_entities(1, &mut doc);
+        assert_eq!(doc.entities[0].target, "Slime");
+        assert_eq!(doc.entities[0].redirected_from, Some("Slimes".to_string()));
+        assert_eq!(doc.entities[0].target_id, Some(1024));
+        assert_eq!(doc.entities[1].target, "Slime");
+        assert_eq!(doc.entities[1].redirected_from, None);
+        assert_eq!(doc.entities[1].target_id, Some(1024));
+
+        // linking twice changes nothing
+        linker.link_entities(1, &mut doc);
+        assert_eq!(doc.entities[0].redirected_from, Some("Slimes".to_string()));
+
+        let mut doc = Doc::parse("{|\n| [[Slimes]]\n|}")?;
+        linker.link_entities(1, &mut doc);
+        let entity = &doc.tables[0].rows[0][0].entities[0];
+        assert_eq!(entity.target, "Slime");
+        assert_eq!(entity.target_id, Some(1024));
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_entities_redirect_to_section() -> Result<(), Error> {
+        let linker = sample_linker();
+        let mut doc = Doc::parse("Drops [[slimeball]]s and [[Slimeball#Uses|more]].")?;
+        linker.link_entities(1, &mut doc);
+        assert_eq!(doc.entities[0].target, "Slime");
+        assert_eq!(doc.entities[0].anchor, Some("Drops".to_string()));
+        // the link's own anchor wins
+        assert_eq!(doc.entities[1].target, "Slime");
+        assert_eq!(doc.entities[1].anchor, Some("Uses".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_entities_red_link() -> Result<(), Error> {
+        let linker = sample_linker();
+        let mut doc = Doc::parse("Make [[iron bar|bars]] from [[Iron Ore]]. See [[#Usage]].")?;
+        linker.link_entities(1, &mut doc);
+        // titles are case-sensitive except for the first letter
+        assert_eq!(doc.entities[0].target_id, None);
+        assert_eq!(doc.entities[1].target_id, None);
+        assert_eq!(doc.entities[2].target_id, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_outside() {
+        let mut tutorial = title(2, "Tutorial:Mining");
+        tutorial.ns = 10000;
+        let titles = vec![title(1, "Slime"), tutorial];
+        let linker = Linker::new(&titles, Vec::new(), sample_namespaces(), &sample_interwikis());
+        assert!(!linker.is_outside("Gel"));
+        assert!(!linker.is_outside("Tutorial:Farming"));
+        assert!(!linker.is_outside("Minecraft: Story Mode"));
+        assert!(linker.is_outside("Ja:鉱石"));
+        assert!(linker.is_outside("Category:Ores"));
+    }
+
+    #[test]
+    fn test_collect_red_links() -> Result<(), Error> {
+        let linker = sample_linker();
+        let docs = vec![
+            (3, Doc::parse("[[Slimes]] drop [[Gel]] and [[Gel|gels]].")?),
+            (2, Doc::parse("Craft [[Gel]] into [[Torch]]es. See [[ja:ゲル]] and [[:Category:Gel]].")?),
+            (4, Doc::parse("Slimes appear in [[Minecraft: Story Mode]].")?),
+            (5, Doc::parse("{{Mob|drops=[[Gel]]}}\n{|\n| [[Torch]] || [[Slime]]\n|}")?),
+        ];
+        let red_links = collect_red_links(docs, &linker);
+        assert_eq!(red_links.len(), 3);
+        assert_eq!(red_links[0].target, "Gel");
+        // including the ones in properties and tables
+        assert_eq!(red_links[0].count, 4);
+        assert_eq!(red_links[0].source_ids, "2 3 5");
+        assert_eq!(red_links[1].target, "Torch");
+        assert_eq!(red_links[1].count, 2);
+        assert_eq!(red_links[1].source_ids, "2 5");
+        assert_eq!(red_links[2].target, "Minecraft: Story Mode");
+        assert_eq!(red_links[2].source_ids, "4");
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Revision of a page which a markuped text was taken from
+///
+/// Revision fields are None when the source doesn't tell them, e.g. `action=raw`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PageMeta {
+    pub revid: Option<u32>,
+    /// When the revision was saved, e.g. "2018-11-01T00:00:00Z"
+    pub timestamp: Option<String>,
+    /// e.g. "wikitext"
+    pub content_model: Option<String>,
+    /// Size of the revision in bytes
+    pub size: Option<u32>,
+    /// SHA-1 of the revision in hex
+    pub sha1: Option<String>,
+    /// When the text was fetched or imported, in the same format as `timestamp`
+    pub fetched_at: String,
+}
+
+impl PageMeta {
+    /// Metadata of a text fetched just now without revision information
+    pub fn fetched_now() -> PageMeta {
+        PageMeta {
+            fetched_at: now_timestamp(),
+            ..PageMeta::default()
+        }
+    }
+}
+
+/// Format seconds since the Unix epoch as MediaWiki does, e.g. "2018-11-01T00:00:00Z"
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // see: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+pub fn now_timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format_timestamp(secs)
+}
+
+/// Convert SHA-1 in base 36, which XML dumps use, into hex, which the API uses
+pub fn base36_to_hex(base36: &str) -> Option<String> {
+    // big-endian bytes of a 160-bit number
+    let mut bytes = [0u8; 20];
+    for c in base36.chars() {
+        let mut carry = c.to_digit(36)?;
+        for b in bytes.iter_mut().rev() {
+            let v = u32::from(*b) * 36 + carry;
+            *b = (v & 0xff) as u8;
+            carry = v >> 8;
+        }
+        if carry != 0 {
+            return None;
+        }
+    }
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(1_541_030_400), "2018-11-01T00:00:00Z");
+        assert_eq!(format_timestamp(951_827_696), "2000-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn test_base36_to_hex() {
+        assert_eq!(
+            base36_to_hex("0").unwrap(),
+            "0000000000000000000000000000000000000000"
+        );
+        assert_eq!(
+            base36_to_hex("zz").unwrap(),
+            "000000000000000000000000000000000000050f"
+        );
+        // SHA-1 of the empty string
+        assert_eq!(
+            base36_to_hex("phoiac9h4m842xq45sp7s6u21eteeq1").unwrap(),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert!(base36_to_hex("not base36!").is_none());
+    }
+}
@@ -0,0 +1,148 @@
+use url::percent_encoding::percent_decode;
+
+use title::Namespace;
+
+/// Link target split into a page title and a section
+#[derive(Debug, PartialEq)]
+pub struct NormalizedTarget {
+    pub title: String,
+    pub anchor: Option<String>,
+}
+
+/// Normalize link targets into titles as MediaWiki does
+///
+/// see: https://www.mediawiki.org/wiki/Manual:Page_title
+pub struct TitleNormalizer {
+    namespaces: Vec<Namespace>,
+}
+
+impl Default for TitleNormalizer {
+    /// Normalizer for wikis with `$wgCapitalLinks = true` which knows no namespaces
+    fn default() -> TitleNormalizer {
+        TitleNormalizer {
+            namespaces: Vec::new(),
+        }
+    }
+}
+
+/// Replace underscores with spaces and squash consecutive spaces
+fn normalize_spaces(s: &str) -> String {
+    s.replace('_', " ")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn decode(s: &str) -> String {
+    percent_decode(s.as_bytes()).decode_utf8_lossy().into_owned()
+}
+
+fn capitalize_first(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+impl TitleNormalizer {
+    /// Use the namespace table of the wiki to resolve namespace prefixes and their case rules
+    pub fn new(namespaces: Vec<Namespace>) -> TitleNormalizer {
+        TitleNormalizer { namespaces }
+    }
+
+    fn is_case_sensitive(&self, ns: i32) -> bool {
+        self.namespaces
+            .iter()
+            .find(|n| n.id == ns)
+            .is_some_and(|n| n.case == "case-sensitive")
+    }
+
+    /// Find a namespace by its local or canonical name, ignoring case
+    fn find_namespace(&self, prefix: &str) -> Option<&Namespace> {
+        self.namespaces
+            .iter()
+            .find(|n| n.id != 0 && n.has_name(prefix))
+    }
+
+    pub fn normalize(&self, raw: &str) -> NormalizedTarget {
+        // split first so that an encoded `#` (`%23`) isn't taken as the separator
+        let (title, anchor) = match raw.find('#') {
+            Some(i) => (&raw[..i], Some(normalize_spaces(&decode(&raw[i + 1..])))),
+            None => (raw, None),
+        };
+        let title = normalize_spaces(&decode(title));
+        // a leading colon only forces a link to a category or file page
+        let title = title.strip_prefix(':').unwrap_or(&title).trim_start();
+
+        let title = match title.find(':').and_then(|i| {
+            self.find_namespace(title[..i].trim())
+                .map(|ns| (ns, title[i + 1..].trim()))
+        }) {
+            Some((ns, rest)) if self.is_case_sensitive(ns.id) => format!("{}:{}", ns.name, rest),
+            Some((ns, rest)) => format!("{}:{}", ns.name, capitalize_first(rest)),
+            None if self.is_case_sensitive(0) => title.to_string(),
+            None => capitalize_first(title),
+        };
+        NormalizedTarget {
+            title,
+            anchor: anchor.filter(|a| !a.is_empty()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn namespace(id: i32, case: &str, canonical: Option<&str>, name: &str) -> Namespace {
+        Namespace {
+            id,
+            case: case.to_string(),
+            canonical: canonical.map(|c| c.to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    fn title(normalizer: &TitleNormalizer, raw: &str) -> String {
+        normalizer.normalize(raw).title
+    }
+
+    #[test]
+    fn test_normalize_default() {
+        let normalizer = TitleNormalizer::default();
+        assert_eq!(title(&normalizer, "iron ore"), "Iron ore");
+        assert_eq!(title(&normalizer, "Iron_Ore"), "Iron Ore");
+        assert_eq!(title(&normalizer, ":Iron Ore"), "Iron Ore");
+        assert_eq!(title(&normalizer, "  Iron __ Ore "), "Iron Ore");
+        assert_eq!(title(&normalizer, "Iron%20Ore"), "Iron Ore");
+        assert_eq!(title(&normalizer, "%C3%A9rc"), "Érc");
+        assert_eq!(title(&normalizer, "ärc"), "Ärc");
+        assert_eq!(title(&normalizer, "鉱石"), "鉱石");
+
+        let target = normalizer.normalize("Iron_Ore#Crafting_recipe");
+        assert_eq!(target.title, "Iron Ore");
+        assert_eq!(target.anchor, Some("Crafting recipe".to_string()));
+        let target = normalizer.normalize("#Usage");
+        assert_eq!(target.title, "");
+        assert_eq!(target.anchor, Some("Usage".to_string()));
+        assert_eq!(normalizer.normalize("Iron Ore#").anchor, None);
+        let target = normalizer.normalize("C%23_Edition#Crafting%20recipe");
+        assert_eq!(target.title, "C# Edition");
+        assert_eq!(target.anchor, Some("Crafting recipe".to_string()));
+    }
+
+    #[test]
+    fn test_normalize_with_namespaces() {
+        let normalizer = TitleNormalizer::new(vec![
+            namespace(0, "case-sensitive", None, ""),
+            namespace(14, "first-letter", Some("Category"), "Kategorie"),
+            namespace(10000, "case-sensitive", None, "Tutorial"),
+        ]);
+        assert_eq!(title(&normalizer, "iron ore"), "iron ore");
+        assert_eq!(title(&normalizer, ":category:erz"), "Kategorie:Erz");
+        assert_eq!(title(&normalizer, "KATEGORIE : erz"), "Kategorie:Erz");
+        assert_eq!(title(&normalizer, "tutorial:mining"), "Tutorial:mining");
+        assert_eq!(title(&normalizer, "ja:鉱石"), "ja:鉱石");
+    }
+}
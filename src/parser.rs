@@ -1,27 +1,132 @@
-use std::fs::File;
-use std::io::prelude::*;
+use std::collections::BTreeMap;
 use std::panic;
 
 use failure::Error;
-use parse_wiki_text::{Configuration, Node};
+use indicatif::ProgressBar;
+use parse_wiki_text::{Configuration, Node, Parameter, TableCaption, TableCellType, TableRow};
+
+use client::for_each_concurrently;
+use data::Data;
+use meta::PageMeta;
+use normalize::TitleNormalizer;
+
+mod config;
+mod offset;
+mod table;
+mod tokenizer;
+
+pub use self::config::{HeadingRules, InfoboxRules, ParseConfig, TemplateRule, TemplateRules};
+pub use self::offset::OffsetUnit;
+use self::offset::OffsetConverter;
+use self::table::{expand_spans, parse_spans};
+pub use self::table::{Cell, Table};
+pub use self::tokenizer::{
+    tokenizer_from_name, CjkTokenizer, Token, Tokenizer, WhitespaceTokenizer,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Doc {
+    pub text: String,
+    /// Unit of `Entity.start/end`; files written before this field existed count bytes
+    #[serde(default)]
+    pub offset_unit: OffsetUnit,
+    pub entities: Vec<Entity>,
+    /// Sections in order of their headings; text before the first heading belongs to none
+    #[serde(default)]
+    pub sections: Vec<Section>,
+    /// Wikitables, which are left out of `text`
+    #[serde(default)]
+    pub tables: Vec<Table>,
+    /// Named parameters of infoboxes; the first infobox wins for the same name
+    #[serde(default)]
+    pub properties: BTreeMap<String, Property>,
+    /// Revision which the doc was parsed from, if recorded when fetched
+    pub meta: Option<PageMeta>,
+    /// Text which the doc was parsed from; files written before this field existed are from
+    /// raw texts
+    #[serde(default)]
+    pub source: TextSource,
+}
 
-use data::{Data, parse_pageid};
+/// Directory of the text which a doc was parsed from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TextSource {
+    /// `raw`
+    #[default]
+    Raw,
+    /// `expanded`, whose templates are expanded by the wiki
+    Expanded,
+}
 
-#[derive(Serialize, Debug)]
-pub struct Doc {
-    text: String,
-    entities: Vec<Entity>,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entity {
+    pub start: u32,
+    pub end: u32,
+    pub repr: String,
+    /// Normalized title of the linked page
+    pub target: String,
+    /// Target as written in the wikitext; empty in files written before this field existed
+    #[serde(default)]
+    pub raw_target: String,
+    /// Section of the linked page, e.g. "Crafting" of `[[Iron Ore#Crafting]]`
+    pub anchor: Option<String>,
+    /// Page id of `target`, filled by `link::link_all_entities`; None for red links
+    pub target_id: Option<u32>,
+    /// Original target if `target` was rewritten from a redirect
+    pub redirected_from: Option<String>,
+}
+
+/// Section of a page, spanning `Doc.text` from its heading to the next heading of the same
+/// or higher level, so that it contains its subsections
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Section {
+    pub heading: String,
+    /// 2 for `== Heading ==`
+    pub level: u8,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Value of an infobox parameter
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Property {
+    pub text: String,
+    /// Links in the value, whose `start/end` count positions in `text`
+    pub entities: Vec<Entity>,
+}
+
+/// Options to customize `Doc::parse_with_options` for each wiki
+#[derive(Default)]
+pub struct ParseOptions {
+    pub normalizer: TitleNormalizer,
+    pub headings: HeadingRules,
+    pub templates: TemplateRules,
+    pub infoboxes: InfoboxRules,
+    /// Parse the expanded text of a page instead of its markuped text if it exists
+    pub expanded: bool,
 }
 
-#[derive(Serialize, Debug)]
-struct Entity {
-    start: u32,
-    end: u32,
-    repr: String,
-    target: String,
+impl ParseOptions {
+    /// Build options from the namespace table and the parse config saved in the data
+    /// directory if any
+    pub fn from_data(data: &Data) -> Result<ParseOptions, Error> {
+        let config = data.load_parse_config()?;
+        Ok(ParseOptions {
+            normalizer: if data.namespace_file.exists() {
+                TitleNormalizer::new(data.load_namespaces()?)
+            } else {
+                TitleNormalizer::default()
+            },
+            headings: HeadingRules::from_config(&config)?,
+            templates: TemplateRules::from_config(&config),
+            infoboxes: InfoboxRules::from_config(&config)?,
+            expanded: config.expanded,
+        })
+    }
 }
 
-fn collect_text<'a>(nodes: &Vec<Node<'a>>) -> Vec<&'a str> {
+fn collect_text<'a>(nodes: &[Node<'a>]) -> Vec<&'a str> {
     nodes
         .iter()
         .filter_map(|node| match node {
@@ -30,93 +135,716 @@ fn collect_text<'a>(nodes: &Vec<Node<'a>>) -> Vec<&'a str> {
         }).collect()
 }
 
-fn is_heading_should_break(nodes: &Vec<Node>) -> bool {
-    let texts = collect_text(nodes);
-    match texts.first() {
-        Some(t) => *t == "References" || *t == "History" || *t == "Video" || *t == "Gallery",
-        None => false,
+/// Return the trimmed text of nodes such as headings and template parameters
+fn plain_text(nodes: &[Node]) -> String {
+    collect_text(nodes).concat().trim().to_string()
+}
+
+/// Return the value of a template parameter by its name or position counted from 1
+fn template_parameter<'p, 'a>(
+    parameters: &'p [Parameter<'a>],
+    key: &str,
+) -> Option<&'p [Node<'a>]> {
+    let mut position = 0;
+    for parameter in parameters {
+        let matched = match parameter.name {
+            Some(ref name) => plain_text(name) == key,
+            None => {
+                position += 1;
+                position.to_string() == key
+            }
+        };
+        if matched {
+            return Some(&parameter.value);
+        }
     }
+    None
 }
 
-impl Doc {
-    pub fn parse(text: &str) -> Result<Doc, Error> {
-        let res = panic::catch_unwind(|| {
-            let mut count = 0;
-            let mut doc_text = String::new();
-            let mut entities = Vec::new();
-            let result = Configuration::default().parse(text);
-            for node in result.nodes {
-                match node {
-                    Node::Text { value, .. } => {
-                        count += value.len();
-                        doc_text.push_str(value);
-                    }
-                    Node::Link { target, text, .. } => {
-                        let start = count as u32;
-                        let mut repr = String::new();
-                        let inner_text = collect_text(&text);
-                        for val in inner_text {
-                            count += val.len();
-                            doc_text.push_str(val);
-                            repr.push_str(val);
-                        }
-                        let end = count as u32;
-                        entities.push(Entity {
-                            start,
-                            end,
-                            repr,
-                            target: target.to_string(),
-                        })
+/// Extension tags whose content is shown as prose
+const PROSE_TAGS: &[&str] = &["nowiki", "poem", "pre"];
+
+/// Plain text, entities and sections being extracted from wikitext nodes
+struct DocBuilder<'o> {
+    options: &'o ParseOptions,
+    text: String,
+    entities: Vec<Entity>,
+    sections: Vec<Section>,
+    /// Indices of sections whose ends haven't been found
+    open_sections: Vec<usize>,
+    /// Level of the heading whose section is being skipped
+    skipped_level: Option<u8>,
+    tables: Vec<Table>,
+    properties: BTreeMap<String, Property>,
+}
+
+impl<'o> DocBuilder<'o> {
+    fn new(options: &'o ParseOptions) -> DocBuilder<'o> {
+        DocBuilder {
+            options,
+            text: String::new(),
+            entities: Vec::new(),
+            sections: Vec::new(),
+            open_sections: Vec::new(),
+            skipped_level: None,
+            tables: Vec::new(),
+            properties: BTreeMap::new(),
+        }
+    }
+
+    /// Start a new line unless the text is empty or already at the start of a line
+    fn ensure_newline(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+    }
+
+    /// Return false if parsing should stop at a heading
+    fn push_nodes(&mut self, nodes: &[Node]) -> bool {
+        nodes.iter().all(|node| self.push_node(node))
+    }
+
+    fn push_node(&mut self, node: &Node) -> bool {
+        if let Node::Heading { level, ref nodes, .. } = *node {
+            return self.push_heading(level, nodes);
+        }
+        if self.skipped_level.is_some() {
+            return true;
+        }
+        match node {
+            Node::Text { value, .. } => self.text.push_str(value),
+            Node::CharacterEntity { character, .. } => self.text.push(*character),
+            Node::Link { target, text, .. } => self.push_link(target, text),
+            Node::ExternalLink { nodes, .. } => self.push_external_link(nodes),
+            Node::Template {
+                name, parameters, ..
+            } => self.push_template(name, parameters),
+            Node::Table { captions, rows, .. } => self.push_table(captions, rows),
+            Node::UnorderedList { items, .. } | Node::OrderedList { items, .. } => {
+                for item in items {
+                    self.ensure_newline();
+                    if !self.push_nodes(&item.nodes) {
+                        return false;
                     }
-                    Node::Heading { ref nodes, .. } if is_heading_should_break(nodes) => break,
-                    Node::ParagraphBreak { .. } | Node::Heading { .. } => {
-                        count += 1;
-                        doc_text.push_str("\n");
+                }
+                self.ensure_newline();
+            }
+            Node::DefinitionList { items, .. } => {
+                for item in items {
+                    self.ensure_newline();
+                    if !self.push_nodes(&item.nodes) {
+                        return false;
                     }
-                    _ => (),
                 }
+                self.ensure_newline();
+            }
+            Node::Preformatted { nodes, .. } => {
+                self.ensure_newline();
+                if !self.push_nodes(nodes) {
+                    return false;
+                }
+                self.ensure_newline();
+            }
+            Node::Tag { name, nodes, .. } if PROSE_TAGS.contains(&&name[..]) => {
+                return self.push_nodes(nodes);
+            }
+            Node::ParagraphBreak { .. } => self.text.push('\n'),
+            _ => (),
+        }
+        true
+    }
+
+    fn push_heading(&mut self, level: u8, nodes: &[Node]) -> bool {
+        let heading = plain_text(nodes);
+        if self.options.headings.should_stop(&heading) {
+            return false;
+        }
+        match self.skipped_level {
+            Some(skipped) if level > skipped => return true,
+            _ => self.skipped_level = None,
+        }
+        while let Some(&i) = self.open_sections.last() {
+            if self.sections[i].level < level {
+                break;
+            }
+            self.sections[i].end = self.text.len() as u32;
+            self.open_sections.pop();
+        }
+        if self.options.headings.should_skip(&heading) {
+            self.skipped_level = Some(level);
+            return true;
+        }
+        self.text.push('\n');
+        self.open_sections.push(self.sections.len());
+        self.sections.push(Section {
+            heading,
+            level,
+            start: self.text.len() as u32,
+            end: self.text.len() as u32,
+        });
+        true
+    }
+
+    fn push_link(&mut self, target: &str, text: &[Node]) {
+        let start = self.text.len();
+        for node in text {
+            match node {
+                Node::Text { value, .. } => self.text.push_str(value),
+                Node::CharacterEntity { character, .. } => self.text.push(*character),
+                _ => (),
             }
-            Doc {
-                text: doc_text,
-                entities,
+        }
+        let normalized = self.options.normalizer.normalize(target);
+        self.entities.push(Entity {
+            start: start as u32,
+            end: self.text.len() as u32,
+            repr: self.text[start..].to_string(),
+            target: normalized.title,
+            raw_target: target.to_string(),
+            anchor: normalized.anchor,
+            target_id: None,
+            redirected_from: None,
+        })
+    }
+
+    /// Push a link template as a link if it has a rule, or record parameters of an infobox
+    fn push_template(&mut self, name: &[Node], parameters: &[Parameter]) {
+        let options = self.options;
+        let name = plain_text(name);
+        if options.infoboxes.is_infobox(&name) {
+            self.push_infobox(parameters);
+            return;
+        }
+        let rule = match options.templates.get(&name) {
+            Some(rule) => rule,
+            None => return,
+        };
+        let target_nodes = match template_parameter(parameters, &rule.target) {
+            Some(nodes) => nodes,
+            None => return,
+        };
+        let target = plain_text(target_nodes);
+        if target.is_empty() {
+            return;
+        }
+        let text = rule
+            .text
+            .as_ref()
+            .and_then(|key| template_parameter(parameters, key))
+            .unwrap_or(target_nodes);
+        self.push_link(&target, text);
+    }
+
+    fn push_infobox(&mut self, parameters: &[Parameter]) {
+        for parameter in parameters {
+            let name = match parameter.name {
+                Some(ref name) => plain_text(name),
+                None => continue,
+            };
+            if name.is_empty() || self.properties.contains_key(&name) {
+                continue;
+            }
+            let (text, entities) = self.build_fragment(&parameter.value);
+            if !text.is_empty() {
+                self.properties.insert(name, Property { text, entities });
             }
+        }
+    }
+
+    /// Return the text and entities of nodes in a cell, caption or parameter, collecting
+    /// nested tables
+    fn build_fragment(&mut self, nodes: &[Node]) -> (String, Vec<Entity>) {
+        let mut builder = DocBuilder::new(self.options);
+        builder.push_nodes(nodes);
+        self.tables.append(&mut builder.tables);
+        let trimmed = builder.text.trim_start().len();
+        let shift = (builder.text.len() - trimmed) as u32;
+        let text = builder.text.trim().to_string();
+        let mut entities = builder.entities;
+        for entity in &mut entities {
+            entity.start = entity.start.saturating_sub(shift).min(text.len() as u32);
+            entity.end = entity.end.saturating_sub(shift).min(text.len() as u32);
+        }
+        (text, entities)
+    }
+
+    fn push_table(&mut self, captions: &[TableCaption], rows: &[TableRow]) {
+        let caption = captions
+            .first()
+            .map(|caption| self.build_fragment(&caption.content).0);
+        let mut spanned_rows = Vec::new();
+        for row in rows {
+            let mut cells = Vec::new();
+            for cell in &row.cells {
+                let (text, entities) = self.build_fragment(&cell.content);
+                let spans = match cell.attributes {
+                    Some(ref attributes) => parse_spans(&collect_text(attributes).concat()),
+                    None => (1, 1),
+                };
+                let cell = Cell {
+                    text,
+                    header: cell.type_ == TableCellType::Heading,
+                    entities,
+                };
+                cells.push((cell, spans.0, spans.1));
+            }
+            spanned_rows.push(cells);
+        }
+        let section = self
+            .open_sections
+            .last()
+            .map(|&i| self.sections[i].heading.clone());
+        self.tables.push(Table {
+            caption,
+            section,
+            rows: expand_spans(spanned_rows),
+        });
+    }
+
+    /// Push the label of `[https://example.com label]` without the URL
+    fn push_external_link(&mut self, nodes: &[Node]) {
+        let mut label_started = false;
+        for node in nodes {
+            match node {
+                Node::Text { value, .. } if !label_started => {
+                    if let Some(i) = value.find(char::is_whitespace) {
+                        self.text.push_str(value[i..].trim_start());
+                        label_started = true;
+                    }
+                }
+                _ if !label_started => (),
+                Node::Text { value, .. } => self.text.push_str(value),
+                Node::CharacterEntity { character, .. } => self.text.push(*character),
+                _ => (),
+            }
+        }
+    }
+
+    fn build(mut self) -> Doc {
+        for i in self.open_sections {
+            self.sections[i].end = self.text.len() as u32;
+        }
+        Doc {
+            text: self.text,
+            offset_unit: OffsetUnit::Byte,
+            entities: self.entities,
+            sections: self.sections,
+            tables: self.tables,
+            properties: self.properties,
+            meta: None,
+            source: TextSource::Raw,
+        }
+    }
+}
+
+impl Doc {
+    pub fn parse(text: &str) -> Result<Doc, Error> {
+        Doc::parse_with_options(text, &ParseOptions::default())
+    }
+
+    pub fn parse_with_options(text: &str, options: &ParseOptions) -> Result<Doc, Error> {
+        let res = panic::catch_unwind(|| {
+            let result = Configuration::default().parse(text);
+            let mut builder = DocBuilder::new(options);
+            builder.push_nodes(&result.nodes);
+            builder.build()
         });
         match res {
             Ok(doc) => Ok(doc),
             Err(_) => Err(format_err!("parse failed"))
         }
     }
-}
 
-pub fn parse_all_markuped_text(data_dir: &str) -> Result<(), Error> {
-    let data = Data::new(data_dir);
-    for entry in data.markuped_text_files()? {
-        match entry {
-            Ok(path) => {
-                let pageid = parse_pageid(&path);
-                let mut file = File::open(path)?;
-                let mut text = String::new();
-                file.read_to_string(&mut text)?;
-                let res = Doc::parse(&text[..]);
-                match res {
-                    Ok(doc) => data.save_parsed_text(pageid, &doc)?,
-                    Err(_) => eprintln!("page {} parse failed", pageid),
-                }
-            }
-            Err(e) => {
-                eprintln!("{:?}", e);
+    /// Rewrite `Entity.start/end` and `Section.start/end` so that they count positions in `unit`
+    pub fn convert_offsets(&mut self, unit: OffsetUnit) -> Result<(), Error> {
+        if unit == self.offset_unit {
+            return Ok(());
+        }
+        let converter = OffsetConverter::new(&self.text, self.offset_unit, unit);
+        for entity in &mut self.entities {
+            entity.start = converter.convert(entity.start as usize)? as u32;
+            entity.end = converter.convert(entity.end as usize)? as u32;
+        }
+        for section in &mut self.sections {
+            section.start = converter.convert(section.start as usize)? as u32;
+            section.end = converter.convert(section.end as usize)? as u32;
+        }
+        let from = self.offset_unit;
+        for (text, entities) in self.fragments_mut() {
+            let converter = OffsetConverter::new(text, from, unit);
+            for entity in entities {
+                entity.start = converter.convert(entity.start as usize)? as u32;
+                entity.end = converter.convert(entity.end as usize)? as u32;
             }
         }
+        self.offset_unit = unit;
+        Ok(())
     }
+
+    /// Return the text and entities of each table cell and property
+    pub(crate) fn fragments_mut<'a>(
+        &'a mut self,
+    ) -> impl Iterator<Item = (&'a str, &'a mut Vec<Entity>)> + 'a {
+        let cells = self
+            .tables
+            .iter_mut()
+            .flat_map(|t| t.rows.iter_mut().flatten())
+            .map(|c| (&c.text[..], &mut c.entities));
+        let properties = self
+            .properties
+            .values_mut()
+            .map(|p| (&p.text[..], &mut p.entities));
+        cells.chain(properties)
+    }
+
+    /// Return `(start, end)` of each entity in bytes whatever `offset_unit` is
+    pub fn entity_byte_spans(&self) -> Result<Vec<(usize, usize)>, Error> {
+        let converter = OffsetConverter::new(&self.text, self.offset_unit, OffsetUnit::Byte);
+        self.entities
+            .iter()
+            .map(|e| {
+                Ok((
+                    converter.convert(e.start as usize)?,
+                    converter.convert(e.end as usize)?,
+                ))
+            }).collect()
+    }
+}
+
+/// Parse the markuped text of a page and save the doc
+///
+/// Failure of parsing is only reported since some pages crash the wikitext parser.
+pub fn parse_markuped_text(
+    data: &Data,
+    pageid: u32,
+    options: &ParseOptions,
+    offset_unit: OffsetUnit,
+) -> Result<(), Error> {
+    let (text, source) = if options.expanded && data.has_expanded_text(pageid) {
+        (data.load_expanded_text(pageid)?, TextSource::Expanded)
+    } else {
+        if options.expanded {
+            eprintln!("page {} has no expanded text; parsing its raw text", pageid);
+        }
+        (data.load_markuped_text(pageid)?, TextSource::Raw)
+    };
+    match Doc::parse_with_options(&text[..], options) {
+        Ok(mut doc) => {
+            doc.convert_offsets(offset_unit)?;
+            doc.meta = data.load_page_meta(pageid)?;
+            doc.source = source;
+            data.save_parsed_text(pageid, &doc)
+        }
+        Err(_) => {
+            eprintln!("page {} parse failed", pageid);
+            Ok(())
+        }
+    }
+}
+
+/// Parse all markuped texts on `workers` threads
+///
+/// Each doc is saved to its own file, so the output is the same whatever `workers` is.
+pub fn parse_all_markuped_text(
+    data_dir: &str,
+    offset_unit: OffsetUnit,
+    workers: usize,
+) -> Result<(), Error> {
+    let data = Data::new(data_dir);
+    let options = ParseOptions::from_data(&data)?;
+    let mut pageids: Vec<u32> = data
+        .make_pageid_set_from_markuped_text_files()?
+        .into_iter()
+        .collect();
+    pageids.sort();
+    let pb = ProgressBar::new(pageids.len() as u64);
+    for_each_concurrently(&pageids, workers, |&pageid| {
+        parse_markuped_text(&data, pageid, &options, offset_unit)?;
+        pb.inc(1);
+        Ok(())
+    })?;
+    pb.finish_with_message("done");
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
     use super::*;
 
+    fn slice(text: &str, start: u32, end: u32, unit: OffsetUnit) -> String {
+        let (start, end) = (start as usize, end as usize);
+        match unit {
+            OffsetUnit::Byte => text[start..end].to_string(),
+            OffsetUnit::Char => text.chars().skip(start).take(end - start).collect(),
+            OffsetUnit::Utf16 => {
+                let units: Vec<u16> = text.encode_utf16().collect();
+                String::from_utf16(&units[start..end]).unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_offset_units() -> Result<(), Error> {
+        let text = r#"'''鉱石'''（こうせき）は[[Overworld|オーバーワールド]]の地下で採れる[[ブロック]]である。
+
+[[Érc]]や[[Minerai|𝄞 minerai]]とも呼ばれる。
+"#;
+        for &unit in &[OffsetUnit::Byte, OffsetUnit::Char, OffsetUnit::Utf16] {
+            let mut doc = Doc::parse(text)?;
+            doc.convert_offsets(unit)?;
+            assert_eq!(doc.offset_unit, unit);
+            assert_eq!(doc.entities.len(), 4);
+            for entity in &doc.entities {
+                assert_eq!(slice(&doc.text, entity.start, entity.end, unit), entity.repr);
+            }
+            let spans = doc.entity_byte_spans()?;
+            assert_eq!(&doc.text[spans[3].0..spans[3].1], "𝄞 minerai");
+        }
+
+        let mut doc = Doc::parse(text)?;
+        doc.convert_offsets(OffsetUnit::Utf16)?;
+        let json = serde_json::to_string(&doc)?;
+        assert!(json.contains(r#""offset_unit":"utf16""#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_normalizes_targets() -> Result<(), Error> {
+        let doc = Doc::parse("Smelt [[iron_ore#Smelting|ore]] in a [[:furnace]].")?;
+        assert_eq!(doc.entities[0].target, "Iron ore");
+        assert_eq!(doc.entities[0].raw_target, "iron_ore#Smelting");
+        assert_eq!(doc.entities[0].anchor, Some("Smelting".to_string()));
+        assert_eq!(doc.entities[1].target, "Furnace");
+        assert_eq!(doc.entities[1].anchor, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_doc_without_new_fields() -> Result<(), Error> {
+        // a doc written before offsets, targets and sections were extended
+        let json = r#"{"text": "Mine ore.", "entities": [
+            {"start": 5, "end": 8, "repr": "ore", "target": "ore"}
+        ]}"#;
+        let doc: Doc = serde_json::from_str(json)?;
+        assert_eq!(doc.offset_unit, OffsetUnit::Byte);
+        assert_eq!(doc.entities[0].raw_target, "");
+        assert_eq!(doc.entities[0].target_id, None);
+        assert!(doc.sections.is_empty());
+        assert_eq!(doc.source, TextSource::Raw);
+        Ok(())
+    }
+
     #[test]
-    fn test_parse_ore() {
+    fn test_parse_heading_rules() -> Result<(), Error> {
+        let text = "Intro.
+== Usage ==
+Use [[ore]].
+== Trivia ==
+Skipped.
+=== Note ===
+Also skipped.
+== Crafting ==
+Craft it.
+== 脚注 ==
+Stopped.
+";
+        let options = ParseOptions {
+            headings: HeadingRules {
+                stop: vec!["脚注".to_string()],
+                skip: vec!["Trivia".to_string()],
+            },
+            ..ParseOptions::default()
+        };
+        let doc = Doc::parse_with_options(text, &options)?;
+        assert!(doc.text.contains("Use ore."));
+        assert!(doc.text.contains("Craft it."));
+        assert!(!doc.text.contains("skipped"));
+        assert!(!doc.text.contains("Skipped"));
+        assert!(!doc.text.contains("Stopped"));
+        assert_eq!(doc.entities.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_sections() -> Result<(), Error> {
+        let text = "Intro.
+== Obtaining ==
+Mine it.
+=== Mining ===
+Use a [[pickaxe]].
+== Usage ==
+Smelt it.
+";
+        for &unit in &[OffsetUnit::Byte, OffsetUnit::Utf16] {
+            let mut doc = Doc::parse(text)?;
+            doc.convert_offsets(unit)?;
+            let headings: Vec<(&str, u8)> = doc
+                .sections
+                .iter()
+                .map(|s| (&s.heading[..], s.level))
+                .collect();
+            assert_eq!(headings, vec![("Obtaining", 2), ("Mining", 3), ("Usage", 2)]);
+            let body = |i: usize| {
+                let section = &doc.sections[i];
+                slice(&doc.text, section.start, section.end, unit)
+            };
+            assert!(body(0).contains("Mine it."));
+            assert!(body(0).contains("Use a pickaxe."));
+            assert!(!body(0).contains("Smelt"));
+            assert!(body(1).contains("Use a pickaxe."));
+            assert!(!body(1).contains("Mine"));
+            assert!(body(2).contains("Smelt it."));
+            assert_eq!(doc.sections[2].end as usize, unit.len(&doc.text));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lists_and_inline_nodes() -> Result<(), Error> {
+        let text = "Ores drop:
+* [[Coal]]
+* [[Diamond|diamonds]]
+*# nested &amp; ordered
+;Term
+:Definition with [https://example.com an external link].
+<nowiki>[[not a link]]</nowiki> and [https://example.com/bare].
+";
+        let doc = Doc::parse(text)?;
+        let lines: Vec<&str> = doc.text.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "Ores drop:",
+                "Coal",
+                "diamonds",
+                "nested & ordered",
+                "Term",
+                "Definition with an external link.",
+                "[[not a link]] and .",
+            ]
+        );
+        assert_eq!(doc.entities.len(), 2);
+        for entity in &doc.entities {
+            assert_eq!(&doc.text[entity.start as usize..entity.end as usize], entity.repr);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_link_templates() -> Result<(), Error> {
+        let text = "{{BlockLink|id=iron-ore|Iron Ore|Iron}} is found in the {{EnvLink|Overworld}} \
+and smelted into {{ItemLink|Iron Ingot}}. {{Stub}}{{MobLink|name=Zombie}}";
+        let mut config = ParseConfig::default();
+        config.link_templates.insert(
+            "MobLink".to_string(),
+            TemplateRule {
+                target: "name".to_string(),
+                text: None,
+            },
+        );
+        let options = ParseOptions {
+            templates: TemplateRules::from_config(&config),
+            ..ParseOptions::default()
+        };
+        let doc = Doc::parse_with_options(text, &options)?;
+        assert_eq!(
+            doc.text,
+            "Iron is found in the Overworld and smelted into Iron Ingot. Zombie"
+        );
+        let links: Vec<(&str, &str)> = doc
+            .entities
+            .iter()
+            .map(|e| (&e.repr[..], &e.target[..]))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                ("Iron", "Iron Ore"),
+                ("Overworld", "Overworld"),
+                ("Iron Ingot", "Iron Ingot"),
+                ("Zombie", "Zombie"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_tables() -> Result<(), Error> {
+        let text = r#"Ores:
+{| class="wikitable"
+|+ Ore [[drops]]
+|-
+! Ore !! Drop
+|-
+| rowspan="2" | [[Iron Ore|Iron]]
+| [[Raw Iron]]
+|-
+| colspan="2" | nothing
+|}
+"#;
+        let mut doc = Doc::parse(text)?;
+        assert_eq!(doc.text.trim(), "Ores:");
+        doc.convert_offsets(OffsetUnit::Char)?;
+        let table = &doc.tables[0];
+        assert_eq!(table.caption, Some("Ore drops".to_string()));
+        let texts: Vec<Vec<&str>> = table
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|c| &c.text[..]).collect())
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                vec!["Ore", "Drop"],
+                vec!["Iron", "Raw Iron"],
+                vec!["Iron", "nothing", "nothing"],
+            ]
+        );
+        assert!(table.rows[0][0].header);
+        let entity = &table.rows[2][0].entities[0];
+        assert_eq!((entity.start, entity.end), (0, 4));
+        assert_eq!(entity.target, "Iron Ore");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_infobox() -> Result<(), Error> {
+        let text = r#"{{Block
+| type = [[Natural blocks]]
+| tool = [[Pickaxe]]
+| renewable = No
+| image =
+}}
+{{Infobox ore|tool=Axe|drops=[[Raw Iron]] &amp; [[Stone]]}}
+'''Iron Ore''' is a mineral block."#;
+        let doc = Doc::parse(text)?;
+        assert_eq!(doc.text.trim(), "Iron Ore is a mineral block.");
+        let names: Vec<&str> = doc.properties.keys().map(String::as_str).collect();
+        assert_eq!(names, vec!["drops", "renewable", "tool", "type"]);
+        assert_eq!(doc.properties["tool"].text, "Pickaxe");
+        let drops = &doc.properties["drops"];
+        assert_eq!(drops.text, "Raw Iron & Stone");
+        let targets: Vec<&str> = drops.entities.iter().map(|e| &e.target[..]).collect();
+        assert_eq!(targets, vec!["Raw Iron", "Stone"]);
+
+        let config = ParseConfig {
+            infobox_templates: Some(vec!["^Infobox ore$".to_string()]),
+            ..ParseConfig::default()
+        };
+        let options = ParseOptions {
+            infoboxes: InfoboxRules::from_config(&config)?,
+            ..ParseOptions::default()
+        };
+        let doc = Doc::parse_with_options(text, &options)?;
+        assert_eq!(doc.properties["tool"].text, "Axe");
+        assert!(!doc.properties.contains_key("type"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ore() -> Result<(), Error> {
         let text = r#"[[File:Ore Layout.png|thumb|300px||All of the current ores.]]
 '''Ores''' are rare stone blocks used to obtain resources.
 
@@ -350,14 +1078,26 @@ File:Pentavein.png|Five different ore veins.
 [[zh:矿石]]
 "#;
         let doc = Doc::parse(text)?;
-        eprintln!("{:#?}", doc);
-        eprintln!("{}", doc.text);
-        eprintln!("{:#?}", serde_json::to_string_pretty(&doc));
-        assert!(false);
+        assert!(doc.text.starts_with("\nOres are rare stone blocks"));
+        assert_eq!(doc.entities.len(), 10);
+        assert_eq!(doc.entities[0].repr, "crafting");
+        let table = &doc.tables[0];
+        assert_eq!(table.header_rows().count(), 1);
+        assert_eq!(table.rows[0][0].text, "Ore type");
+        assert_eq!(table.rows[2][0].text, "Iron");
+        assert_eq!(table.rows[2][0].entities[0].target, "Iron Ore");
+        assert_eq!(table.rows[2][6].text, "Overworld");
+        for row in &table.rows {
+            assert_eq!(row.len(), 7);
+        }
+        for entity in &doc.entities {
+            assert_eq!(&doc.text[entity.start as usize..entity.end as usize], entity.repr);
+        }
+        Ok(())
     }
 
     #[test]
-    fn test_parse_armor() {
+    fn test_parse_armor() -> Result<(), Error> {
         let text = r#"{{Item
 |image=
 Leather Cap.png;
@@ -1018,8 +1758,64 @@ File:SkeletonRiderGroup.png|[[Skeleton]]s spawned from a skeleton trap [[horse]]
 [[zh:盔甲]]
 "#;
         let doc = Doc::parse(text)?;
-        eprintln!("{:#?}", doc);
-        eprintln!("{}", doc.text);
-        assert!(false);
+        // including links in the lists of damage sources and link templates
+        assert_eq!(doc.entities.len(), 68);
+        assert_eq!(doc.properties["invimage"].text, "Leather Cap");
+        assert_eq!(doc.properties["invimage7"].text, "----");
+        assert_eq!(doc.entities[0].repr, "players");
+        assert_eq!(doc.entities[0].target, "Player");
+        assert_eq!(doc.entities[0].raw_target, "player");
+        for entity in &doc.entities {
+            assert_eq!(&doc.text[entity.start as usize..entity.end as usize], entity.repr);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_expanded_text() -> Result<(), Error> {
+        let data = Data::new("test_dir_parse_expanded_text");
+        data.save_markuped_text(1, "{{Ore description|Iron}}")?;
+        data.save_expanded_text(1, "[[Iron Ore]] is an [[ore]].")?;
+        let mut options = ParseOptions::default();
+        parse_markuped_text(&data, 1, &options, OffsetUnit::Byte)?;
+        let doc = data.load_parsed_text(1)?;
+        assert_eq!(doc.text, "");
+        assert_eq!(doc.source, TextSource::Raw);
+        options.expanded = true;
+        parse_markuped_text(&data, 1, &options, OffsetUnit::Byte)?;
+        let doc = data.load_parsed_text(1)?;
+        assert_eq!(doc.text, "Iron Ore is an ore.");
+        assert_eq!(doc.entities.len(), 2);
+        assert_eq!(doc.source, TextSource::Expanded);
+        // falls back to the raw text without expanded one
+        data.remove_expanded_text(1)?;
+        parse_markuped_text(&data, 1, &options, OffsetUnit::Byte)?;
+        assert_eq!(data.load_parsed_text(1)?.source, TextSource::Raw);
+        fs::remove_dir_all(data.base_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_all_markuped_text_deterministic() -> Result<(), Error> {
+        let data_dir = "test_dir_parse_all_markuped_text";
+        let data = Data::new(data_dir);
+        for pageid in 1..=20 {
+            let text = format!("Page {} links [[ore]] and [[Block|blocks]].", pageid);
+            data.save_markuped_text(pageid, &text[..])?;
+        }
+        let read_docs = || -> Result<Vec<String>, Error> {
+            (1..=20)
+                .map(|pageid| {
+                    let path = data.parsed_text_dir.join(format!("{}.json", pageid));
+                    Ok(fs::read_to_string(path)?)
+                }).collect()
+        };
+        parse_all_markuped_text(data_dir, OffsetUnit::Char, 1)?;
+        let serial = read_docs()?;
+        parse_all_markuped_text(data_dir, OffsetUnit::Char, 4)?;
+        assert_eq!(read_docs()?, serial);
+        assert!(serial[19].contains("Page 20 links ore and blocks."));
+        fs::remove_dir_all(data_dir)?;
+        Ok(())
     }
 }
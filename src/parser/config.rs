@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashMap};
+
+use failure::Error;
+use regex::Regex;
+
+/// Settings of parsing for each wiki, saved as `parse_config.json` in the data directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct ParseConfig {
+    /// Language whose preset of headings is used, "en" (default) or "ja"
+    pub language: Option<String>,
+    /// Replace the headings of the preset at which parsing stops
+    pub stop_headings: Option<Vec<String>>,
+    /// Replace the headings of the preset whose sections are skipped
+    pub skip_headings: Option<Vec<String>>,
+    /// Add rules of link templates to the built-in ones, keyed by template name
+    pub link_templates: BTreeMap<String, TemplateRule>,
+    /// Parse texts in `expanded` instead of `raw` where they exist
+    pub expanded: bool,
+    /// Replace the built-in regex patterns of infobox template names
+    pub infobox_templates: Option<Vec<String>>,
+}
+
+/// Headings of sections which aren't worth parsing, e.g. references and galleries
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadingRules {
+    /// Parsing stops at the first of these headings
+    pub stop: Vec<String>,
+    /// Sections under these headings are skipped until the next heading of the same or
+    /// higher level
+    pub skip: Vec<String>,
+}
+
+impl Default for HeadingRules {
+    fn default() -> HeadingRules {
+        HeadingRules::preset("en").unwrap()
+    }
+}
+
+fn to_strings(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+impl HeadingRules {
+    /// Return the headings commonly put at the end of pages in the language
+    pub fn preset(language: &str) -> Result<HeadingRules, Error> {
+        match language {
+            "en" => Ok(HeadingRules {
+                stop: to_strings(&["References", "History", "Video", "Gallery"]),
+                skip: Vec::new(),
+            }),
+            "ja" => Ok(HeadingRules {
+                stop: to_strings(&["脚注", "出典", "参考文献", "歴史", "動画", "ギャラリー"]),
+                skip: Vec::new(),
+            }),
+            _ => Err(format_err!(
+                "no preset of headings for language: {}",
+                language
+            )),
+        }
+    }
+
+    pub fn from_config(config: &ParseConfig) -> Result<HeadingRules, Error> {
+        let mut rules =
+            HeadingRules::preset(config.language.as_ref().map_or("en", String::as_str))?;
+        if let Some(ref stop) = config.stop_headings {
+            rules.stop = stop.clone();
+        }
+        if let Some(ref skip) = config.skip_headings {
+            rules.skip = skip.clone();
+        }
+        Ok(rules)
+    }
+
+    pub fn should_stop(&self, heading: &str) -> bool {
+        self.stop.iter().any(|h| h == heading)
+    }
+
+    pub fn should_skip(&self, heading: &str) -> bool {
+        self.skip.iter().any(|h| h == heading)
+    }
+}
+
+/// Which parameters of a link template are its target and display text
+///
+/// Parameters are named or numbered from 1 in order of unnamed ones as MediaWiki does.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TemplateRule {
+    pub target: String,
+    /// The target is displayed if None or the parameter is absent
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+/// Link templates turned into text and entities, e.g. `{{ItemLink|Iron Ingot|ingots}}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateRules {
+    rules: HashMap<String, TemplateRule>,
+}
+
+/// Link templates of Gamepedia wikis such as Minecraft Wiki
+const BUILTIN_LINK_TEMPLATES: &[&str] = &[
+    "BlockLink",
+    "ItemLink",
+    "EnvLink",
+    "EntityLink",
+    "BiomeLink",
+    "EffectLink",
+];
+
+impl Default for TemplateRules {
+    fn default() -> TemplateRules {
+        let rule = TemplateRule {
+            target: "1".to_string(),
+            text: Some("2".to_string()),
+        };
+        TemplateRules {
+            rules: BUILTIN_LINK_TEMPLATES
+                .iter()
+                .map(|name| (template_key(name), rule.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// Template names are case-insensitive only in the first letter and `_` means a space
+fn template_key(name: &str) -> String {
+    let name = name.trim().replace('_', " ");
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+impl TemplateRules {
+    pub fn from_config(config: &ParseConfig) -> TemplateRules {
+        let mut rules = TemplateRules::default();
+        for (name, rule) in &config.link_templates {
+            rules.rules.insert(template_key(name), rule.clone());
+        }
+        rules
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TemplateRule> {
+        self.rules.get(&template_key(name))
+    }
+}
+
+/// Infoboxes of Gamepedia wikis and Wikipedia, e.g. `{{Block|...}}` and `{{Infobox mineral|...}}`
+const BUILTIN_INFOBOX_PATTERNS: &[&str] = &[
+    r"(?i)infobox",
+    r"^(Block|Item|Entity|Mob|Biome|Effect|Enchantment)$",
+];
+
+/// Templates whose parameters are facts about the page
+#[derive(Debug, Clone)]
+pub struct InfoboxRules {
+    patterns: Vec<Regex>,
+}
+
+impl Default for InfoboxRules {
+    fn default() -> InfoboxRules {
+        InfoboxRules {
+            patterns: BUILTIN_INFOBOX_PATTERNS
+                .iter()
+                .map(|p| Regex::new(p).unwrap())
+                .collect(),
+        }
+    }
+}
+
+impl InfoboxRules {
+    pub fn from_config(config: &ParseConfig) -> Result<InfoboxRules, Error> {
+        match config.infobox_templates {
+            Some(ref patterns) => Ok(InfoboxRules {
+                patterns: patterns
+                    .iter()
+                    .map(|p| Regex::new(p))
+                    .collect::<Result<Vec<Regex>, _>>()?,
+            }),
+            None => Ok(InfoboxRules::default()),
+        }
+    }
+
+    /// Whether a template is an infobox, matching its name normalized like `TemplateRules`
+    pub fn is_infobox(&self, name: &str) -> bool {
+        let key = template_key(name);
+        self.patterns.iter().any(|re| re.is_match(&key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heading_rules_from_config() -> Result<(), Error> {
+        let config: ParseConfig =
+            serde_json::from_str(r#"{"language": "ja", "skip_headings": ["関連項目"]}"#)?;
+        let rules = HeadingRules::from_config(&config)?;
+        assert!(rules.should_stop("脚注"));
+        assert!(!rules.should_stop("References"));
+        assert!(rules.should_skip("関連項目"));
+
+        let rules = HeadingRules::from_config(&ParseConfig::default())?;
+        assert_eq!(rules, HeadingRules::default());
+        assert!(rules.should_stop("Gallery"));
+
+        let config = ParseConfig {
+            language: Some("xx".to_string()),
+            ..ParseConfig::default()
+        };
+        assert!(HeadingRules::from_config(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_rules_from_config() -> Result<(), Error> {
+        let config: ParseConfig = serde_json::from_str(
+            r#"{"link_templates": {"Mob_link": {"target": "name"}, "ItemLink": {"target": "2"}}}"#,
+        )?;
+        let rules = TemplateRules::from_config(&config);
+        assert_eq!(rules.get("blockLink").unwrap().text, Some("2".to_string()));
+        assert_eq!(rules.get("ItemLink").unwrap().target, "2");
+        assert_eq!(rules.get(" mob link ").unwrap().target, "name");
+        assert!(rules.get("Infobox").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_infobox_rules_from_config() -> Result<(), Error> {
+        let rules = InfoboxRules::default();
+        assert!(rules.is_infobox("Infobox mineral"));
+        assert!(rules.is_infobox("block"));
+        assert!(!rules.is_infobox("BlockLink"));
+
+        let config: ParseConfig = serde_json::from_str(r#"{"infobox_templates": ["^Ore$"]}"#)?;
+        let rules = InfoboxRules::from_config(&config)?;
+        assert!(rules.is_infobox("ore"));
+        assert!(!rules.is_infobox("Block"));
+
+        let config: ParseConfig = serde_json::from_str(r#"{"infobox_templates": ["("]}"#)?;
+        assert!(InfoboxRules::from_config(&config).is_err());
+        Ok(())
+    }
+}
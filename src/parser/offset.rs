@@ -0,0 +1,106 @@
+use failure::Error;
+
+/// Unit in which `Entity.start/end` count positions in `Doc.text`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OffsetUnit {
+    /// UTF-8 bytes, as Rust slices strings
+    #[default]
+    Byte,
+    /// Unicode scalar values, as Python slices strings
+    Char,
+    /// UTF-16 code units, as JavaScript and Java slice strings
+    Utf16,
+}
+
+impl OffsetUnit {
+    /// Return a unit by its name: "byte", "char" or "utf16"
+    pub fn from_name(name: &str) -> Result<OffsetUnit, Error> {
+        match name {
+            "byte" => Ok(OffsetUnit::Byte),
+            "char" => Ok(OffsetUnit::Char),
+            "utf16" => Ok(OffsetUnit::Utf16),
+            _ => Err(format_err!("unknown offset unit: {}", name)),
+        }
+    }
+
+    fn len_char(self, c: char) -> usize {
+        match self {
+            OffsetUnit::Byte => c.len_utf8(),
+            OffsetUnit::Char => 1,
+            OffsetUnit::Utf16 => c.len_utf16(),
+        }
+    }
+
+    /// Return the length of text in this unit
+    pub fn len(self, text: &str) -> usize {
+        match self {
+            OffsetUnit::Byte => text.len(),
+            _ => text.chars().map(|c| self.len_char(c)).sum(),
+        }
+    }
+}
+
+/// Return the offsets of every char boundary in text, including both ends
+///
+/// The i-th element points the same position whichever unit is given, so offsets can be
+/// converted between units by looking up the index.
+fn boundaries(text: &str, unit: OffsetUnit) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    let mut offset = 0;
+    offsets.push(offset);
+    for c in text.chars() {
+        offset += unit.len_char(c);
+        offsets.push(offset);
+    }
+    offsets
+}
+
+/// Convert offsets into text from one unit to another
+pub struct OffsetConverter {
+    from: Vec<usize>,
+    to: Vec<usize>,
+}
+
+impl OffsetConverter {
+    pub fn new(text: &str, from: OffsetUnit, to: OffsetUnit) -> OffsetConverter {
+        OffsetConverter {
+            from: boundaries(text, from),
+            to: boundaries(text, to),
+        }
+    }
+
+    pub fn convert(&self, offset: usize) -> Result<usize, Error> {
+        match self.from.binary_search(&offset) {
+            Ok(i) => Ok(self.to[i]),
+            Err(_) => Err(format_err!("offset {} is not on a char boundary", offset)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_unit_len() {
+        let text = "Érc 鉱石 𝄞";
+        assert_eq!(OffsetUnit::Byte.len(text), 16);
+        assert_eq!(OffsetUnit::Char.len(text), 8);
+        assert_eq!(OffsetUnit::Utf16.len(text), 9);
+    }
+
+    #[test]
+    fn test_offset_converter() -> Result<(), Error> {
+        let text = "Érc 鉱石 𝄞";
+        let converter = OffsetConverter::new(text, OffsetUnit::Byte, OffsetUnit::Utf16);
+        assert_eq!(converter.convert(0)?, 0);
+        assert_eq!(converter.convert(5)?, 4);
+        assert_eq!(converter.convert(16)?, 9);
+        assert!(converter.convert(1).is_err());
+
+        let converter = OffsetConverter::new(text, OffsetUnit::Utf16, OffsetUnit::Char);
+        assert_eq!(converter.convert(9)?, 8);
+        Ok(())
+    }
+}
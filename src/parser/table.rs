@@ -0,0 +1,171 @@
+use regex::Regex;
+
+use super::Entity;
+
+/// Wikitable expanded into a grid
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Table {
+    pub caption: Option<String>,
+    /// Heading of the innermost section containing the table, None before the first heading
+    #[serde(default)]
+    pub section: Option<String>,
+    /// A cell spanning multiple rows or columns is repeated in each of them, so that every
+    /// row has a cell for each column it reaches. Columns missing in a row before a cell
+    /// spanning down to it are filled with empty cells.
+    pub rows: Vec<Vec<Cell>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cell {
+    pub text: String,
+    /// Whether the cell is a header cell (`!`)
+    pub header: bool,
+    /// Links in the cell, whose `start/end` count positions in `text`
+    pub entities: Vec<Entity>,
+}
+
+impl Table {
+    /// Rows whose cells are all header cells
+    pub fn header_rows(&self) -> impl Iterator<Item = &Vec<Cell>> {
+        self.rows
+            .iter()
+            .filter(|row| !row.is_empty() && row.iter().all(|cell| cell.header))
+    }
+}
+
+/// Upper bound of `rowspan` and `colspan` against malformed tables
+const MAX_SPAN: usize = 1000;
+
+/// Return `(rowspan, colspan)` given by the attributes of a cell, e.g. `rowspan="2"`
+pub fn parse_spans(attributes: &str) -> (usize, usize) {
+    lazy_static! {
+        static ref SPAN_RE: Regex =
+            Regex::new(r#"(?i)\b(rowspan|colspan)\s*=\s*["']?\s*(\d+)"#).unwrap();
+    }
+    let mut spans = (1, 1);
+    for caps in SPAN_RE.captures_iter(attributes) {
+        let span = caps[2].parse().unwrap_or(1);
+        let span = span.clamp(1, MAX_SPAN);
+        if caps[1].eq_ignore_ascii_case("rowspan") {
+            spans.0 = span;
+        } else {
+            spans.1 = span;
+        }
+    }
+    spans
+}
+
+/// Lay out rows of cells with their `(rowspan, colspan)` in a grid
+pub fn expand_spans(rows: Vec<Vec<(Cell, usize, usize)>>) -> Vec<Vec<Cell>> {
+    // cell spanning down from a previous row and the number of rows it still covers, by column
+    let mut pending: Vec<Option<(Cell, usize)>> = Vec::new();
+    let mut grid = Vec::new();
+    for row in rows {
+        let mut cells = Vec::new();
+        let mut row = row.into_iter();
+        let mut col = 0;
+        loop {
+            if let Some(Some((cell, remaining))) = pending.get_mut(col) {
+                cells.push(cell.clone());
+                *remaining -= 1;
+                if *remaining == 0 {
+                    pending[col] = None;
+                }
+                col += 1;
+                continue;
+            }
+            let (cell, rowspan, colspan) = match row.next() {
+                Some(spanned) => spanned,
+                None if pending.iter().skip(col).any(Option::is_some) => {
+                    // a gap to a cell spanning down to a later column, filled with an empty
+                    // cell so that later columns stay aligned
+                    cells.push(Cell {
+                        text: String::new(),
+                        header: false,
+                        entities: Vec::new(),
+                    });
+                    col += 1;
+                    continue;
+                }
+                None => break,
+            };
+            for _ in 0..colspan {
+                if rowspan > 1 {
+                    if pending.len() <= col {
+                        pending.resize(col + 1, None);
+                    }
+                    pending[col] = Some((cell.clone(), rowspan - 1));
+                }
+                cells.push(cell.clone());
+                col += 1;
+            }
+        }
+        grid.push(cells);
+    }
+    grid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(text: &str) -> Cell {
+        Cell {
+            text: text.to_string(),
+            header: false,
+            entities: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_spans() {
+        assert_eq!(parse_spans(r#"rowspan="2" style="color: red""#), (2, 1));
+        assert_eq!(parse_spans("COLSPAN=3 rowspan='4'"), (4, 3));
+        assert_eq!(parse_spans(r#"colspan="0""#), (1, 1));
+        assert_eq!(parse_spans("class=\"wikitable\""), (1, 1));
+    }
+
+    #[test]
+    fn test_expand_spans() {
+        // | a (rowspan=2) | b (colspan=2) |
+        // |               | c | d         |
+        // | e | f | g                     |
+        let grid = expand_spans(vec![
+            vec![(cell("a"), 2, 1), (cell("b"), 1, 2)],
+            vec![(cell("c"), 1, 1), (cell("d"), 1, 1)],
+            vec![(cell("e"), 1, 1), (cell("f"), 1, 1), (cell("g"), 1, 1)],
+        ]);
+        let texts: Vec<Vec<&str>> = grid
+            .iter()
+            .map(|row| row.iter().map(|c| &c.text[..]).collect())
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                vec!["a", "b", "b"],
+                vec!["a", "c", "d"],
+                vec!["e", "f", "g"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_spans_short_row() {
+        // | a | b | c (rowspan=2) |
+        // | d |   |               |
+        // | e |
+        let grid = expand_spans(vec![
+            vec![(cell("a"), 1, 1), (cell("b"), 1, 1), (cell("c"), 2, 1)],
+            vec![(cell("d"), 1, 1)],
+            vec![(cell("e"), 1, 1)],
+        ]);
+        let texts: Vec<Vec<&str>> = grid
+            .iter()
+            .map(|row| row.iter().map(|c| &c.text[..]).collect())
+            .collect();
+        assert_eq!(
+            texts,
+            vec![vec!["a", "b", "c"], vec!["d", "", "c"], vec!["e"]]
+        );
+    }
+}
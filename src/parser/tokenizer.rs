@@ -0,0 +1,147 @@
+use failure::Error;
+
+#[derive(Debug, PartialEq)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    /// Byte position in the source text where the token starts
+    pub start: usize,
+    /// Byte position in the source text where the token ends
+    pub end: usize,
+}
+
+/// Split text into tokens keeping track of their positions
+///
+/// Implementations must return tokens in order and must not overlap them, so that
+/// tags can be aligned to `Entity.start/end`.
+pub trait Tokenizer {
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<Token<'a>>;
+}
+
+/// Split text at whitespace, emitting each punctuation character as its own token
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<Token<'a>> {
+        split_tokens(text, |_| false)
+    }
+}
+
+/// Same as `WhitespaceTokenizer` but emit each CJK character as its own token
+///
+/// Japanese and Chinese don't put spaces between words, so splitting into characters is
+/// the only way to align tags without a dictionary.
+pub struct CjkTokenizer;
+
+impl Tokenizer for CjkTokenizer {
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<Token<'a>> {
+        split_tokens(text, is_cjk)
+    }
+}
+
+/// Return a tokenizer by its name: "whitespace" or "cjk"
+pub fn tokenizer_from_name(name: &str) -> Result<Box<dyn Tokenizer>, Error> {
+    match name {
+        "whitespace" => Ok(Box::new(WhitespaceTokenizer)),
+        "cjk" => Ok(Box::new(CjkTokenizer)),
+        _ => Err(format_err!("unknown tokenizer: {}", name)),
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    match c as u32 {
+        // Hiragana, Katakana
+        0x3040..=0x30FF | 0x31F0..=0x31FF | 0xFF66..=0xFF9F => true,
+        // CJK Unified Ideographs and their extensions, compatibility ideographs
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x2FA1F => true,
+        _ => false,
+    }
+}
+
+/// Split text at whitespace and punctuation, and around characters satisfying `is_single`
+fn split_tokens<F: Fn(char) -> bool>(text: &str, is_single: F) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut token_start = None;
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() && !is_single(c) {
+            if token_start.is_none() {
+                token_start = Some(i);
+            }
+            continue;
+        }
+        if let Some(start) = token_start.take() {
+            tokens.push(Token {
+                text: &text[start..i],
+                start,
+                end: i,
+            });
+        }
+        if !c.is_whitespace() {
+            let end = i + c.len_utf8();
+            tokens.push(Token {
+                text: &text[i..end],
+                start: i,
+                end,
+            });
+        }
+    }
+    if let Some(start) = token_start {
+        tokens.push(Token {
+            text: &text[start..],
+            start,
+            end: text.len(),
+        });
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts<'a>(tokens: &[Token<'a>]) -> Vec<&'a str> {
+        tokens.iter().map(|t| t.text).collect()
+    }
+
+    #[test]
+    fn test_whitespace_tokenizer() {
+        let tokens = WhitespaceTokenizer.tokenize("Iron Ore, (rare).");
+        assert_eq!(
+            texts(&tokens),
+            vec!["Iron", "Ore", ",", "(", "rare", ")", "."]
+        );
+        assert_eq!(tokens[1].start, 5);
+        assert_eq!(tokens[1].end, 8);
+    }
+
+    #[test]
+    fn test_cjk_tokenizer() {
+        let tokens = CjkTokenizer.tokenize("鉄鉱石はOverworldで採れる。");
+        assert_eq!(
+            texts(&tokens),
+            vec![
+                "鉄",
+                "鉱",
+                "石",
+                "は",
+                "Overworld",
+                "で",
+                "採",
+                "れ",
+                "る",
+                "。"
+            ]
+        );
+        assert_eq!(tokens[4].start, 12);
+        assert_eq!(tokens[4].end, 21);
+
+        let tokens = CjkTokenizer.tokenize("철광석 矿石");
+        assert_eq!(texts(&tokens), vec!["철광석", "矿", "石"]);
+    }
+
+    #[test]
+    fn test_tokenizer_from_name() {
+        assert!(tokenizer_from_name("whitespace").is_ok());
+        assert!(tokenizer_from_name("cjk").is_ok());
+        assert!(tokenizer_from_name("mecab").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use failure::Error;
+
+use api::QueryClient;
+use client::HttpClient;
+use title::Namespace;
+
+/// A redirect page and the page it points
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Redirect {
+    pub from: String,
+    pub to: String,
+    /// None if the target page doesn't exist
+    pub to_id: Option<u32>,
+    /// Section of the target page, e.g. "Crafting" of "Iron Ore#Crafting"
+    pub fragment: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MWRedirect {
+    from: String,
+    to: String,
+    tofragment: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MWRedirectTarget {
+    // absent if the page is missing
+    pageid: Option<u32>,
+    title: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct MWRedirectsQuery {
+    #[serde(default)]
+    redirects: Vec<MWRedirect>,
+    #[serde(default)]
+    pages: HashMap<String, MWRedirectTarget>,
+}
+
+fn parse_redirects(query: MWRedirectsQuery) -> Vec<Redirect> {
+    let ids: HashMap<String, u32> = query
+        .pages
+        .into_values()
+        .filter_map(|p| p.pageid.map(|id| (p.title, id)))
+        .collect();
+    query
+        .redirects
+        .into_iter()
+        .map(|r| Redirect {
+            to_id: ids.get(&r.to).cloned(),
+            from: r.from,
+            to: r.to,
+            fragment: r.tofragment,
+        })
+        .collect()
+}
+
+/// Return all redirects whose source is in `namespaces`
+pub fn retrieve_all_redirects(
+    url: &str,
+    namespaces: &[Namespace],
+    client: &HttpClient,
+) -> Result<Vec<Redirect>, Error> {
+    // recommended. see: https://www.mediawiki.org/wiki/Manual:Maxlag_parameter/ja
+    let client = QueryClient::new(url, 5, client);
+    let mut redirects = Vec::new();
+    for namespace in namespaces {
+        let namespace_id = namespace.id.to_string();
+        let params = &[
+            ("generator", "allpages"),
+            ("gapfilterredir", "redirects"),
+            ("gapnamespace", &namespace_id[..]),
+            ("gaplimit", "500"),
+            ("redirects", "true"),
+        ];
+        client.query_all(params, |query| {
+            let partial = parse_redirects(query);
+            eprintln!("retrieved {} redirects", partial.len());
+            redirects.extend(partial);
+            Ok(())
+        })?;
+    }
+    Ok(redirects)
+}
+
+pub fn load_redirects<P: AsRef<Path>>(filepath: P) -> Result<Vec<Redirect>, Error> {
+    let mut rdr = csv::Reader::from_path(filepath)?;
+    let mut redirects = Vec::new();
+    for res in rdr.deserialize() {
+        let redirect: Redirect = res?;
+        redirects.push(redirect);
+    }
+    Ok(redirects)
+}
+
+pub fn save_redirects<P: AsRef<Path>>(redirects: &[Redirect], filepath: P) -> Result<(), Error> {
+    let mut wtr = csv::Writer::from_path(filepath)?;
+    for redirect in redirects {
+        wtr.serialize(redirect)?;
+    }
+    Ok(())
+}
+
+/// Look up redirects by their source title
+pub struct RedirectMap {
+    redirects: HashMap<String, Redirect>,
+}
+
+impl RedirectMap {
+    pub fn new(redirects: Vec<Redirect>) -> RedirectMap {
+        RedirectMap {
+            redirects: redirects.into_iter().map(|r| (r.from.clone(), r)).collect(),
+        }
+    }
+
+    /// Return the final redirect reached from `title`, following double redirects
+    ///
+    /// Return None if `title` is not a redirect.
+    pub fn resolve(&self, title: &str) -> Option<&Redirect> {
+        // MediaWiki itself doesn't follow more than one redirect, but wikis often leave
+        // double redirects behind after page moves
+        let max_hops = 5;
+        let mut resolved = self.redirects.get(title)?;
+        for _ in 1..max_hops {
+            match self.redirects.get(&resolved.to) {
+                Some(next) if next.from != title => resolved = next,
+                _ => break,
+            }
+        }
+        Some(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use api::MWQueryApiResponse;
+
+    fn redirect(from: &str, to: &str, to_id: Option<u32>) -> Redirect {
+        Redirect {
+            from: from.to_string(),
+            to: to.to_string(),
+            to_id,
+            fragment: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_redirects() -> Result<(), Error> {
+        let json = r#"{"continue":{"gapcontinue":"Slimes","continue":"gapcontinue||"},"query":{
+            "redirects":[
+                {"from":"Iron Bars","to":"Iron Bar"},
+                {"from":"Slime drops","to":"Slime","tofragment":"Drops"},
+                {"from":"Tin","to":"Tin Ore (removed)"}
+            ],
+            "pages":{
+                "-1":{"ns":0,"title":"Tin Ore (removed)","missing":""},
+                "812":{"pageid":812,"ns":0,"title":"Iron Bar"},
+                "1024":{"pageid":1024,"ns":0,"title":"Slime"}
+            }
+        }}"#;
+        let json: MWQueryApiResponse<MWRedirectsQuery> = serde_json::from_str(json)?;
+        assert_eq!(json._continue.unwrap()["gapcontinue"], "Slimes");
+        let redirects = parse_redirects(json.query.unwrap());
+        assert_eq!(redirects.len(), 3);
+        assert_eq!(redirects[0].from, "Iron Bars");
+        assert_eq!(redirects[0].to, "Iron Bar");
+        assert_eq!(redirects[0].to_id, Some(812));
+        assert_eq!(redirects[1].fragment, Some("Drops".to_string()));
+        assert_eq!(redirects[2].to_id, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_redirect_file_io() -> Result<(), Error> {
+        let tempfile = "tmp_redirects.csv";
+        let redirects = vec![
+            redirect("Slimes", "Slime", Some(1024)),
+            redirect("Tin", "Tin Ore", None),
+        ];
+        save_redirects(&redirects, tempfile)?;
+        let res = load_redirects(tempfile);
+        fs::remove_file(tempfile)?;
+        let loaded = res?;
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].to_id, Some(1024));
+        assert_eq!(loaded[1].to_id, None);
+        assert_eq!(loaded[1].fragment, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_redirect_map_resolve() {
+        let map = RedirectMap::new(vec![
+            redirect("Slimes", "Slime", Some(1024)),
+            redirect("Old Slime", "Slimes", None),
+            redirect("A", "B", None),
+            redirect("B", "A", None),
+        ]);
+        assert_eq!(map.resolve("Slimes").unwrap().to, "Slime");
+        assert_eq!(map.resolve("Old Slime").unwrap().to, "Slime");
+        assert_eq!(map.resolve("Old Slime").unwrap().to_id, Some(1024));
+        assert!(map.resolve("Slime").is_none());
+        // loops must terminate
+        assert!(map.resolve("A").is_some());
+    }
+}
@@ -1,32 +1,99 @@
-use std::{cmp, thread, time};
+use std::fs;
+use std::collections::HashMap;
 use std::path::Path;
 
 use failure::Error;
-use regex::Regex;
+
+use api::{Continuation, QueryClient};
+use client::HttpClient;
+use data::Data;
+use meta::PageMeta;
+
+/// Number of requests between checkpoints of `retrieve_all_titles`
+const CHECKPOINT_INTERVAL: usize = 10;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Title {
     pub id: u32,
     // see: https://www.mediawiki.org/wiki/Manual:Namespace/ja#%E7%B5%84%E3%81%BF%E8%BE%BC%E3%81%BF%E3%81%AE%E5%90%8D%E5%89%8D%E7%A9%BA%E9%96%93
-    ns: i32,
+    pub ns: i32,
+    /// Local name of the namespace, empty for the main namespace
+    #[serde(default)]
+    pub ns_name: String,
     pub name: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Page {
-    title: Title,
-    text: String,
+impl Title {
+    /// Return the name without the namespace prefix
+    pub fn name_in_namespace(&self) -> &str {
+        let prefix = format!("{}:", self.ns_name);
+        if self.ns_name.is_empty() || !self.name.starts_with(&prefix[..]) {
+            &self.name[..]
+        } else {
+            &self.name[prefix.len()..]
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct MWError {
-    code: String,
-    info: String,
+/// An entry of the wiki's namespace table
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Namespace {
+    pub id: i32,
+    /// "first-letter" or "case-sensitive"
+    pub case: String,
+    /// English name shared by all wikis, absent for the main namespace
+    pub canonical: Option<String>,
+    /// Local name, empty for the main namespace
+    #[serde(rename = "*")]
+    pub name: String,
 }
 
-#[derive(Deserialize, Debug)]
-struct MWContinue {
-    apcontinue: String,
+impl Namespace {
+    /// Whether `name` is the local or canonical name of this namespace, ignoring case
+    pub(crate) fn has_name(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.name.to_lowercase() == name
+            || self.canonical.as_ref().is_some_and(|c| c.to_lowercase() == name)
+    }
+
+    /// Whether `spec` designates this namespace by its id, local name or canonical name
+    ///
+    /// The main namespace can be designated by "Main" as it has no name.
+    fn is_designated_by(&self, spec: &str) -> bool {
+        let spec = spec.trim().replace('_', " ").to_lowercase();
+        spec == self.id.to_string()
+            || spec == self.name.to_lowercase()
+            || self.canonical.as_ref().is_some_and(|c| c.to_lowercase() == spec)
+            || (self.id == 0 && (spec == "main" || spec == "(main)"))
+    }
+}
+
+/// Pick namespaces designated by `specs` (ids or names) from the namespace table
+pub fn select_namespaces(table: &[Namespace], specs: &[&str]) -> Result<Vec<Namespace>, Error> {
+    specs
+        .iter()
+        .map(|spec| {
+            table
+                .iter()
+                .find(|ns| ns.is_designated_by(spec))
+                .cloned()
+                .ok_or_else(|| format_err!("unknown namespace: {}", spec))
+        }).collect()
+}
+
+/// An entry of the wiki's interwiki table, including interlanguage links
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Interwiki {
+    /// e.g. "ja" for `[[ja:鉱石]]`
+    pub prefix: String,
+    pub url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Page {
+    pub title: Title,
+    pub text: String,
+    pub meta: Option<PageMeta>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -43,78 +110,80 @@ struct MWQuery {
 }
 
 #[derive(Deserialize, Debug)]
-struct MWAllpagesApiResponse {
-    error: Option<MWError>,
-    #[serde(rename = "continue")]
-    _continue: Option<MWContinue>,
-    query: Option<MWQuery>,
+struct MWSiteinfoQuery {
+    namespaces: HashMap<String, Namespace>,
+}
+
+/// Return the namespace table of the wiki sorted by id
+pub fn request_namespaces(url: &str, client: &HttpClient) -> Result<Vec<Namespace>, Error> {
+    let params = &[("meta", "siteinfo"), ("siprop", "namespaces")];
+    let (query, _) = QueryClient::new(url, 5, client).query(params, &Continuation::new())?;
+    let query = query.ok_or_else(|| format_err!("no query in response"))?;
+    Ok(parse_namespaces(query))
+}
+
+fn parse_namespaces(query: MWSiteinfoQuery) -> Vec<Namespace> {
+    let mut namespaces: Vec<Namespace> = query.namespaces.into_values().collect();
+    namespaces.sort_by_key(|ns| ns.id);
+    namespaces
+}
+
+#[derive(Deserialize, Debug)]
+struct MWInterwikiQuery {
+    interwikimap: Vec<Interwiki>,
+}
+
+/// Return the interwiki table of the wiki
+pub fn request_interwikis(url: &str, client: &HttpClient) -> Result<Vec<Interwiki>, Error> {
+    let params = &[("meta", "siteinfo"), ("siprop", "interwikimap")];
+    let (query, _) = QueryClient::new(url, 5, client).query(params, &Continuation::new())?;
+    let query: MWInterwikiQuery = query.ok_or_else(|| format_err!("no query in response"))?;
+    Ok(query.interwikimap)
 }
 
-/// Parse time from maxlag info like this: "Waiting for a database server: 0 seconds lagged."
-fn parse_maxlag_waiting_time(info: &str) -> u64 {
-    lazy_static! {
-        static ref MAXLAG_RE: Regex = Regex::new(r"(?P<time>\d+) seconds lagged.$").unwrap();
+/// Convert a batch of `list=allpages` into titles in `namespace`
+fn to_titles(query: MWQuery, namespace: &Namespace) -> Vec<Title> {
+    let titles: Vec<Title> = query
+        .allpages
+        .into_iter()
+        .map(|p| Title {
+            id: p.pageid,
+            ns: p.ns,
+            ns_name: namespace.name.clone(),
+            name: p.title,
+        }).collect();
+    if let (Some(first), Some(last)) = (titles.first(), titles.last()) {
+        eprintln!(
+            "retrieved {} titles: \"{}\" ... \"{}\"",
+            titles.len(),
+            first.name,
+            last.name
+        );
     }
-    let caps = MAXLAG_RE.captures(info).unwrap();
-    caps["time"].parse().unwrap()
+    titles
 }
 
-/// Return Vec of Title and the following name of the last Title
+/// Return Vec of Title from `from` and the continuation of the next batch if any
 ///
-/// You can proceed next request by passing the last name to `from`.
+/// `from` is a name without the namespace prefix.
 fn request_titles_partially(
-    url: &str,
+    client: &QueryClient,
+    namespace: &Namespace,
     limit: u32,
     from: Option<&str>,
-    maxlag: i32,
-) -> Result<(Vec<Title>, Option<String>), Error> {
+) -> Result<(Vec<Title>, Option<Continuation>), Error> {
     let limit = if limit > 500 { 500 } else { limit };
-    let query = &[
-        ("action", "query"),
+    let namespace_id = namespace.id.to_string();
+    let limit = limit.to_string();
+    let params = &[
         ("list", "allpages"),
-        ("format", "json"),
-        ("utf8", "true"),
-        ("aplimit", &limit.to_string()),
+        ("apnamespace", &namespace_id[..]),
+        ("aplimit", &limit[..]),
         ("apfrom", from.unwrap_or("")),
-        ("maxlag", &maxlag.to_string()),
     ];
-    let client = reqwest::Client::new();
-    for _ in 0..5 {
-        let json: MWAllpagesApiResponse = client.get(url).query(query).send()?.json()?;
-        match json.error {
-            Some(e) => match &e.code[..] {
-                "maxlag" => {
-                    let secs = cmp::max(1, parse_maxlag_waiting_time(&e.info));
-                    let dur = time::Duration::from_secs(secs);
-                    eprintln!("maxlag error: retry after {} secs", secs);
-                    thread::sleep(dur);
-                    continue;
-                }
-                _ => return Err(format_err!("unexpected API error: {:?}", e)),
-            },
-            None => {
-                let titles: Vec<Title> = json
-                    .query
-                    .unwrap()
-                    .allpages
-                    .into_iter()
-                    .map(|p| Title {
-                        id: p.pageid,
-                        ns: p.ns,
-                        name: p.title,
-                    }).collect();
-                eprintln!(
-                    "retrieved {} titles: \"{}\" ... \"{}\"",
-                    titles.len(),
-                    titles.first().unwrap().name,
-                    titles.last().unwrap().name
-                );
-                let apcontinue = json._continue.map(|p| p.apcontinue);
-                return Ok((titles, apcontinue));
-            }
-        }
-    }
-    Err(format_err!("retried 5 times but can't receive"))
+    let (query, continuation) = client.query::<MWQuery>(params, &Continuation::new())?;
+    let query = query.ok_or_else(|| format_err!("no query in response"))?;
+    Ok((to_titles(query, namespace), continuation))
 }
 
 pub fn load_titles<P: AsRef<Path>>(filepath: P) -> Result<Vec<Title>, Error> {
@@ -127,46 +196,195 @@ pub fn load_titles<P: AsRef<Path>>(filepath: P) -> Result<Vec<Title>, Error> {
     Ok(titles)
 }
 
+/// Save titles through a temporary file not to break the file when interrupted
 pub fn save_titles<P: AsRef<Path>>(titles: &Vec<Title>, filepath: P) -> Result<(), Error> {
-    let mut wtr = csv::Writer::from_path(filepath)?;
-    for title in titles {
-        wtr.serialize(title)?;
+    let filepath = filepath.as_ref();
+    let tmp_filepath = filepath.with_extension("tmp");
+    {
+        let mut wtr = csv::Writer::from_path(&tmp_filepath)?;
+        for title in titles {
+            wtr.serialize(title)?;
+        }
+        wtr.flush()?;
     }
+    fs::rename(tmp_filepath, filepath)?;
     Ok(())
 }
 
-fn request_next_title(title: &str, url: &str) -> Result<Option<String>, Error> {
-    let (_, next_title) = request_titles_partially(url, 1, Some(title), 5)?;
-    Ok(next_title)
+/// Return the continuation to list titles after `title`, or None if it is the last one
+fn request_next_title(
+    title: &str,
+    namespace: &Namespace,
+    client: &QueryClient,
+) -> Result<Option<Continuation>, Error> {
+    let (_, continuation) = request_titles_partially(client, namespace, 1, Some(title))?;
+    Ok(continuation)
+}
+
+/// Progress of `retrieve_all_titles` saved together with titles
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TitleCheckpoint {
+    /// Number of titles saved with the checkpoint
+    pub count: usize,
+    /// Name of the last of them, to check that `titles.csv` wasn't rewritten since
+    #[serde(default)]
+    pub last_title: Option<String>,
+    /// Namespace being retrieved
+    pub ns: i32,
+    /// Continuation to resume the namespace from, None for its beginning
+    pub continuation: Option<Continuation>,
+    /// Whether all titles in the namespace are retrieved
+    pub completed: bool,
+}
+
+impl TitleCheckpoint {
+    fn new(titles: &[Title], ns: i32, continuation: Option<Continuation>) -> TitleCheckpoint {
+        TitleCheckpoint {
+            count: titles.len(),
+            last_title: titles.last().map(|t| t.name.clone()),
+            ns,
+            completed: continuation.is_none(),
+            continuation,
+        }
+    }
+}
+
+/// Return the index of the namespace to resume and the continuation to resume it from
+///
+/// Titles saved after the checkpoint are dropped since they will be retrieved again.
+/// It fails without dropping any title if `titles` don't match the checkpoint.
+fn resume_from_checkpoint(
+    titles: &mut Vec<Title>,
+    namespaces: &[Namespace],
+    checkpoint: &TitleCheckpoint,
+) -> Result<(usize, Option<Continuation>), Error> {
+    let last_title = match checkpoint.count {
+        0 => None,
+        count => titles.get(count - 1).map(|t| t.name.clone()),
+    };
+    if last_title != checkpoint.last_title {
+        return Err(format_err!(
+            "titles don't match the checkpoint of {} titles ending with {:?}; \
+             remove the checkpoint to resume from the last title",
+            checkpoint.count,
+            checkpoint.last_title
+        ));
+    }
+    let i = namespaces
+        .iter()
+        .position(|ns| ns.id == checkpoint.ns)
+        .ok_or_else(|| {
+            format_err!(
+                "the selected namespaces differ from the checkpoint, which is retrieving \
+                 namespace {}",
+                checkpoint.ns
+            )
+        })?;
+    titles.truncate(checkpoint.count);
+    if checkpoint.completed {
+        Ok((i + 1, None))
+    } else {
+        Ok((i, checkpoint.continuation.clone()))
+    }
+}
+
+/// Same as `resume_from_checkpoint` for titles saved without a checkpoint
+///
+/// Namespaces before the last title's one are regarded as completed.
+fn resume_from_last_title(
+    titles: &[Title],
+    namespaces: &[Namespace],
+    client: &QueryClient,
+) -> Result<(usize, Option<Continuation>), Error> {
+    let title = match titles.last() {
+        Some(title) => title,
+        None => return Ok((0, None)),
+    };
+    let i = namespaces
+        .iter()
+        .position(|ns| ns.id == title.ns)
+        .ok_or_else(|| {
+            format_err!(
+                "the last saved title \"{}\" is in namespace {}, which isn't selected",
+                title.name,
+                title.ns
+            )
+        })?;
+    match request_next_title(title.name_in_namespace(), &namespaces[i], client)? {
+        Some(continuation) => Ok((i, Some(continuation))),
+        // the namespace is already completed
+        None => Ok((i + 1, None)),
+    }
 }
 
-/// Populate parameter `titles` and return Result to save progress
+fn save_checkpoint(
+    data: &Data,
+    titles: &Vec<Title>,
+    checkpoint: &TitleCheckpoint,
+) -> Result<(), Error> {
+    // titles first, so that the checkpoint never counts unsaved titles
+    data.save_titles(titles)?;
+    data.save_title_checkpoint(checkpoint)
+}
+
+/// Retrieve all titles in `namespaces` into `data_dir`
 ///
-/// You can resume retrieving by passing existing titles.
-pub fn retrieve_all_titles(titles: &mut Vec<Title>, url: &str) -> Result<(), Error> {
-    let limit = 500;
+/// Namespaces are retrieved in the given order. Titles are saved with a checkpoint every
+/// `CHECKPOINT_INTERVAL` requests and on failure, and you can resume retrieving by passing
+/// the same namespaces. The checkpoint is removed when all titles are retrieved.
+pub fn retrieve_all_titles(
+    url: &str,
+    data_dir: &str,
+    namespaces: &[Namespace],
+    client: &HttpClient,
+) -> Result<(), Error> {
+    let data = Data::new(data_dir);
+    let limit = 500.to_string();
     // recommended. see: https://www.mediawiki.org/wiki/Manual:Maxlag_parameter/ja
-    let maxlag = 5;
-    // for allocation
-    let mut next_title = match titles.last() {
-        Some(title) => request_next_title(&title.name[..], url)?,
-        None => None,
+    let client = QueryClient::new(url, 5, client);
+    let mut titles = if data.title_file.exists() {
+        data.load_titles()?
+    } else {
+        Vec::new()
     };
-    // wait 1 sec per each request
-    let interval = time::Duration::from_secs(1);
-    loop {
-        let res =
-            request_titles_partially(url, limit, next_title.as_ref().map(String::as_str), maxlag)?;
-        let partial_titles = res.0;
-        titles.extend(partial_titles);
-        next_title = res.1;
-        if next_title.is_some() {
-            thread::sleep(interval);
-        } else {
-            break;
+    let (skip, mut next) = match data.load_title_checkpoint()? {
+        Some(checkpoint) => resume_from_checkpoint(&mut titles, namespaces, &checkpoint)?,
+        None => resume_from_last_title(&titles, namespaces, &client)?,
+    };
+    let mut requests = 0;
+    for namespace in namespaces.iter().skip(skip) {
+        let namespace_id = namespace.id.to_string();
+        let params = &[
+            ("list", "allpages"),
+            ("apnamespace", &namespace_id[..]),
+            ("aplimit", &limit[..]),
+        ];
+        // continuation of the batch being requested, None for the beginning of the namespace
+        let mut current = next.take();
+        let start = current.clone().unwrap_or_default();
+        let res = client.query_all_from(params, start, |query: MWQuery, next_batch| {
+            titles.extend(to_titles(query, namespace));
+            current = next_batch.cloned();
+            requests += 1;
+            if current.is_none() || requests % CHECKPOINT_INTERVAL == 0 {
+                let checkpoint = TitleCheckpoint::new(&titles, namespace.id, current.clone());
+                save_checkpoint(&data, &titles, &checkpoint)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = res {
+            // the checkpoint resumes the namespace from its beginning if current is None
+            // here, so mark it as not completed
+            let checkpoint = TitleCheckpoint {
+                completed: false,
+                ..TitleCheckpoint::new(&titles, namespace.id, current)
+            };
+            save_checkpoint(&data, &titles, &checkpoint)?;
+            return Err(e);
         }
     }
-    Ok(())
+    // titles are complete, so the next run starts over from the last title
+    data.remove_title_checkpoint()
 }
 
 #[cfg(test)]
@@ -174,14 +392,40 @@ mod tests {
     use std::fs;
 
     use super::*;
+    use client::ClientConfig;
+
+    fn main_namespace() -> Namespace {
+        Namespace {
+            id: 0,
+            case: "first-letter".to_string(),
+            canonical: None,
+            name: "".to_string(),
+        }
+    }
+
+    fn apcontinue(continuation: Option<Continuation>) -> Option<String> {
+        continuation.and_then(|mut c| c.remove("apcontinue"))
+    }
+
+    fn sample_namespaces() -> Result<Vec<Namespace>, Error> {
+        let json = r#"{"namespaces":{
+            "-1":{"id":-1,"case":"first-letter","canonical":"Special","*":"Special"},
+            "0":{"id":0,"case":"first-letter","content":"","*":""},
+            "10":{"id":10,"case":"first-letter","canonical":"Template","*":"Template"},
+            "14":{"id":14,"case":"first-letter","canonical":"Category","*":"Kategorie"},
+            "10000":{"id":10000,"case":"first-letter","canonical":"Tutorial","*":"Tutorial"}
+        }}"#;
+        Ok(parse_namespaces(serde_json::from_str(json)?))
+    }
 
     #[test]
     fn test_request_titles_partially() {
         let url = "https://minecraft.gamepedia.com/api.php";
         let limit = 3;
         let from = "Ore";
-        let maxlag = 5;
-        let (titles, next_title) = request_titles_partially(url, limit, Some(from), maxlag)
+        let http = HttpClient::new(&ClientConfig::default()).unwrap();
+        let client = QueryClient::new(url, 5, &http);
+        let (titles, next_title) = request_titles_partially(&client, &main_namespace(), limit, Some(from))
             .expect("Error in calling Allpages API");
         assert_eq!(titles.len(), 3);
         assert_eq!(titles[0].id, 3020);
@@ -193,22 +437,22 @@ mod tests {
         assert_eq!(titles[2].id, 7315);
         assert_eq!(titles[2].ns, 0);
         assert_eq!(titles[2].name, "Ore Block");
-        assert_eq!(next_title, Some("Ores".to_string()));
+        assert_eq!(apcontinue(next_title), Some("Ores".to_string()));
 
         // first element
         let limit = 1;
         let from = None;
-        let (titles, next_title) = request_titles_partially(url, limit, from, maxlag)
+        let (titles, next_title) = request_titles_partially(&client, &main_namespace(), limit, from)
             .expect("Error in calling Allpages API");
         assert_eq!(titles.len(), 1);
         assert_eq!(titles[0].id, 89618);
         assert_eq!(titles[0].ns, 0);
         assert_eq!(titles[0].name, "'Tis but a scratch");
-        assert_eq!(next_title, Some("...has_become_the_master".to_string()));
+        assert_eq!(apcontinue(next_title), Some("...has_become_the_master".to_string()));
 
         // last element
         let from = "Žodynas/lt";
-        let (titles, next_title) = request_titles_partially(url, limit, Some(from), maxlag)
+        let (titles, next_title) = request_titles_partially(&client, &main_namespace(), limit, Some(from))
             .expect("Error in calling Allpages API");
         assert_eq!(titles.len(), 1);
         assert_eq!(titles[0].id, 21373);
@@ -223,22 +467,12 @@ mod tests {
         let limit = 3;
         let from = "Ore";
         let maxlag = -1;
-        let res = request_titles_partially(url, limit, Some(from), maxlag);
+        let http = HttpClient::new(&ClientConfig::default()).unwrap();
+        let client = QueryClient::new(url, maxlag, &http);
+        let res = request_titles_partially(&client, &main_namespace(), limit, Some(from));
         assert!(res.is_err());
     }
 
-    #[test]
-    fn test_parse_maxlag_waiting_time() {
-        assert_eq!(
-            parse_maxlag_waiting_time("Waiting for a database server: 0 seconds lagged."),
-            0
-        );
-        assert_eq!(
-            parse_maxlag_waiting_time("Waiting for a database server: 10 seconds lagged."),
-            10
-        );
-    }
-
     #[test]
     fn test_title_file_io() {
         let tempfile = "tmp.csv";
@@ -246,15 +480,17 @@ mod tests {
             Title {
                 id: 0,
                 ns: 0,
+                ns_name: "".to_string(),
                 name: "a".to_string(),
             },
             Title {
                 id: 1,
                 ns: 0,
+                ns_name: "".to_string(),
                 name: "b".to_string(),
             },
         ];
-        save_titles(&titles_expected, tempfile);
+        save_titles(&titles_expected, tempfile).unwrap();
         let res = load_titles(tempfile);
         assert!(fs::remove_file(tempfile).is_ok());
         let titles_actual = res.unwrap();
@@ -267,15 +503,159 @@ mod tests {
         assert_eq!(titles_actual[1].name, titles_expected[1].name);
     }
 
+    #[test]
+    fn test_title_file_io_with_namespace() -> Result<(), Error> {
+        let tempfile = "tmp_with_namespace.csv";
+        let titles_expected = vec![Title {
+            id: 1,
+            ns: 14,
+            ns_name: "Category".to_string(),
+            name: "Category:b".to_string(),
+        }];
+        save_titles(&titles_expected, tempfile)?;
+        let res = load_titles(tempfile);
+        fs::remove_file(tempfile)?;
+        let titles_actual = res?;
+        assert_eq!(titles_actual.len(), 1);
+        assert_eq!(titles_actual[0].ns, 14);
+        assert_eq!(titles_actual[0].ns_name, "Category");
+        assert_eq!(titles_actual[0].name, "Category:b");
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_from_last_title_in_unselected_namespace() -> Result<(), Error> {
+        let namespaces = select_namespaces(&sample_namespaces()?, &["Main"])?;
+        let titles = vec![Title {
+            id: 1,
+            ns: 14,
+            ns_name: "Kategorie".to_string(),
+            name: "Kategorie:Erz".to_string(),
+        }];
+        let http = HttpClient::new(&ClientConfig::default())?;
+        let client = QueryClient::new("https://minecraft.gamepedia.com/api.php", 5, &http);
+        // fails before any request
+        assert!(resume_from_last_title(&titles, &namespaces, &client).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_titles_without_ns_name() -> Result<(), Error> {
+        let tempfile = "tmp_without_ns_name.csv";
+        fs::write(tempfile, "id,ns,name\n3020,0,Ore\n")?;
+        let res = load_titles(tempfile);
+        fs::remove_file(tempfile)?;
+        let titles = res?;
+        assert_eq!(titles.len(), 1);
+        assert_eq!(titles[0].ns_name, "");
+        assert_eq!(titles[0].name, "Ore");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_namespaces() -> Result<(), Error> {
+        let namespaces = sample_namespaces()?;
+        let ids: Vec<i32> = namespaces.iter().map(|ns| ns.id).collect();
+        assert_eq!(ids, vec![-1, 0, 10, 14, 10000]);
+        assert_eq!(namespaces[1].name, "");
+        assert_eq!(namespaces[3].name, "Kategorie");
+        assert_eq!(namespaces[3].canonical, Some("Category".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_interwikis() -> Result<(), Error> {
+        let json = r#"{"interwikimap":[
+            {"prefix":"ja","local":"","language":"日本語","url":"https://minecraft-ja.gamepedia.com/$1"},
+            {"prefix":"wikipedia","url":"https://en.wikipedia.org/wiki/$1"}
+        ]}"#;
+        let query: MWInterwikiQuery = serde_json::from_str(json)?;
+        assert_eq!(query.interwikimap.len(), 2);
+        assert_eq!(query.interwikimap[0].prefix, "ja");
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_namespaces() -> Result<(), Error> {
+        let table = sample_namespaces()?;
+        let specs = ["Main", "category", "Kategorie", "10", "Tutorial"];
+        let selected = select_namespaces(&table, &specs)?;
+        let ids: Vec<i32> = selected.iter().map(|ns| ns.id).collect();
+        assert_eq!(ids, vec![0, 14, 14, 10, 10000]);
+        assert!(select_namespaces(&table, &["Help"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() -> Result<(), Error> {
+        let namespaces = select_namespaces(&sample_namespaces()?, &["Main", "Category"])?;
+        let title = |id, ns, name: &str| Title {
+            id,
+            ns,
+            ns_name: "".to_string(),
+            name: name.to_string(),
+        };
+        let mut titles = vec![title(1, 0, "A"), title(2, 0, "B"), title(3, 0, "C")];
+        let continuation: Continuation = vec![
+            ("apcontinue".to_string(), "C".to_string()),
+            ("continue".to_string(), "-||".to_string()),
+        ].into_iter()
+        .collect();
+        let checkpoint = TitleCheckpoint::new(&titles[..2], 0, Some(continuation.clone()));
+        assert!(!checkpoint.completed);
+        let resumed = resume_from_checkpoint(&mut titles, &namespaces, &checkpoint)?;
+        assert_eq!(resumed, (0, Some(continuation.clone())));
+        assert_eq!(titles.len(), 2);
+
+        let checkpoint = TitleCheckpoint::new(&titles, 0, None);
+        assert!(checkpoint.completed);
+        let resumed = resume_from_checkpoint(&mut titles, &namespaces, &checkpoint)?;
+        assert_eq!(resumed, (1, None));
+
+        // the selected namespaces differ
+        let checkpoint = TitleCheckpoint::new(&titles, 10, None);
+        assert!(resume_from_checkpoint(&mut titles, &namespaces, &checkpoint).is_err());
+
+        // titles.csv was rewritten after the checkpoint
+        let checkpoint = TitleCheckpoint::new(&titles, 0, Some(continuation));
+        let mut rewritten = vec![title(1, 0, "A"), title(4, 0, "Ab"), title(2, 0, "B")];
+        assert!(resume_from_checkpoint(&mut rewritten, &namespaces, &checkpoint).is_err());
+        assert_eq!(rewritten.len(), 3);
+        let mut shortened = vec![title(1, 0, "A")];
+        assert!(resume_from_checkpoint(&mut shortened, &namespaces, &checkpoint).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_name_in_namespace() {
+        let title = Title {
+            id: 1,
+            ns: 14,
+            ns_name: "Kategorie".to_string(),
+            name: "Kategorie:Erz".to_string(),
+        };
+        assert_eq!(title.name_in_namespace(), "Erz");
+        let title = Title {
+            id: 2,
+            ns: 0,
+            ns_name: "".to_string(),
+            name: "Tutorial:Mining".to_string(),
+        };
+        assert_eq!(title.name_in_namespace(), "Tutorial:Mining");
+    }
+
     #[test]
     fn test_request_next_title() -> Result<(), Error> {
         let url = "https://minecraft.gamepedia.com/api.php";
+        let main = main_namespace();
+        let http = HttpClient::new(&ClientConfig::default())?;
+        let client = QueryClient::new(url, 5, &http);
         assert_eq!(
-            request_next_title("'Tis but a scratch", url)?,
+            apcontinue(request_next_title("'Tis but a scratch", &main, &client)?),
             Some("...has_become_the_master".to_string())
         );
         // last element
-        assert_eq!(request_next_title("Žodynas/lt", url)?, None);
+        assert_eq!(request_next_title("Žodynas/lt", &main, &client)?, None);
         Ok(())
     }
 }
@@ -0,0 +1,507 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use failure::Error;
+use indicatif::ProgressBar;
+
+use api::{expand_markuped_text, request_markuped_texts, QueryClient, MAX_PAGEIDS_PER_REQUEST};
+use client::{for_each_concurrently, HttpClient};
+use data::{parse_pageid, Data};
+use error::Failure;
+use meta::now_timestamp;
+use parser::{parse_markuped_text, OffsetUnit, ParseOptions};
+use title::{Namespace, Title};
+
+/// When the markuped texts were crawled last
+///
+/// `update` retrieves changes made after `last_crawled_at`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CrawlState {
+    /// e.g. "2018-11-01T00:00:00Z"
+    pub last_crawled_at: String,
+}
+
+/// Record the start of a crawl before fetching any page
+///
+/// A crawl resuming with pages fetched before keeps the earlier time recorded, since those
+/// pages may have been edited after they were fetched.
+pub fn record_crawl_start(data: &Data, started_at: &str, resumed: bool) -> Result<(), Error> {
+    if let Some(state) = data.load_crawl_state()? {
+        if resumed && state.last_crawled_at.as_str() <= started_at {
+            return Ok(());
+        }
+    }
+    data.save_crawl_state(&CrawlState {
+        last_crawled_at: started_at.to_string(),
+    })
+}
+
+/// A change of a page which the local copy should follow
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// The page was created, edited or restored
+    Edit { pageid: u32, ns: i32, name: String },
+    /// The page was deleted. Deleted pages are known only by their titles.
+    Delete { ns: i32, name: String },
+    /// The page was renamed to `name`
+    Move { pageid: u32, ns: i32, name: String },
+}
+
+/// Pages to fetch again and to remove
+#[derive(Debug, Default, PartialEq)]
+pub struct Updates {
+    pub fetched: BTreeSet<u32>,
+    pub removed: BTreeSet<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MWRecentChange {
+    #[serde(rename = "type")]
+    _type: String,
+    ns: i32,
+    title: String,
+    pageid: u32,
+    logtype: Option<String>,
+    logaction: Option<String>,
+    // an object for moves but an empty array for deletions
+    #[serde(default)]
+    logparams: serde_json::Value,
+}
+
+impl MWRecentChange {
+    fn into_change(self) -> Option<Change> {
+        match (
+            &self._type[..],
+            self.logtype.as_deref(),
+            self.logaction.as_deref(),
+        ) {
+            ("edit", _, _) | ("new", _, _) | ("log", Some("delete"), Some("restore")) => {
+                Some(Change::Edit {
+                    pageid: self.pageid,
+                    ns: self.ns,
+                    name: self.title,
+                })
+            }
+            ("log", Some("delete"), Some("delete")) => Some(Change::Delete {
+                ns: self.ns,
+                name: self.title,
+            }),
+            ("log", Some("move"), _) => Some(Change::Move {
+                pageid: self.pageid,
+                ns: self.logparams["target_ns"].as_i64()? as i32,
+                name: self.logparams["target_title"].as_str()?.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MWRecentChangesQuery {
+    recentchanges: Vec<MWRecentChange>,
+}
+
+/// Return changes in `namespaces` made after `since` in chronological order
+///
+/// Wikis keep recent changes only for a limited period (90 days by default).
+fn request_recent_changes(
+    client: &QueryClient,
+    since: &str,
+    namespaces: &BTreeSet<i32>,
+) -> Result<Vec<Change>, Error> {
+    let namespaces = namespaces
+        .iter()
+        .map(|ns| ns.to_string())
+        .collect::<Vec<String>>()
+        .join("|");
+    let params = &[
+        ("list", "recentchanges"),
+        ("rcstart", since),
+        ("rcdir", "newer"),
+        ("rcnamespace", &namespaces[..]),
+        ("rcprop", "title|ids|loginfo"),
+        ("rctype", "edit|new|log"),
+        ("rclimit", "500"),
+    ];
+    let mut changes = Vec::new();
+    client.query_all(params, |query: MWRecentChangesQuery| {
+        changes.extend(
+            query
+                .recentchanges
+                .into_iter()
+                .filter_map(MWRecentChange::into_change),
+        );
+        Ok(())
+    })?;
+    Ok(changes)
+}
+
+/// Namespaces which the titles were retrieved from
+fn tracked_namespaces(titles: &[Title]) -> BTreeSet<i32> {
+    titles.iter().map(|t| t.ns).collect()
+}
+
+/// Key which `list=allpages` sorts titles in a namespace by
+fn listing_key(title: &Title) -> String {
+    title.name_in_namespace().replace(' ', "_")
+}
+
+/// Insert `title` before the first title listed after it, so that titles stay in the order
+/// `download_titles` lists them and it can resume from the last title
+///
+/// Namespaces are ranked by their first appearance in `ns_order`.
+fn insert_in_listing_order(titles: &mut Vec<Title>, title: Title, ns_order: &[i32]) {
+    let rank = |ns| ns_order.iter().position(|&n| n == ns);
+    let key = (rank(title.ns), listing_key(&title));
+    let i = titles
+        .iter()
+        .position(|t| (rank(t.ns), listing_key(t)) > key)
+        .unwrap_or(titles.len());
+    titles.insert(i, title);
+}
+
+/// Apply `changes` to `titles` and return pages whose texts should be fetched or removed
+///
+/// Pages in namespaces which `titles` don't cover are ignored, and pages moved out of them
+/// are removed. Created and moved pages are put in listing order.
+pub fn apply_changes(
+    titles: &mut Vec<Title>,
+    namespaces: &[Namespace],
+    changes: &[Change],
+) -> Updates {
+    let tracked = tracked_namespaces(titles);
+    let mut ns_order: Vec<i32> = Vec::new();
+    for title in titles.iter() {
+        if !ns_order.contains(&title.ns) {
+            ns_order.push(title.ns);
+        }
+    }
+    let known: HashSet<u32> = titles.iter().map(|t| t.id).collect();
+    // latest titles of created, edited and moved pages
+    let mut changed: BTreeMap<u32, Title> = BTreeMap::new();
+    let mut updates = Updates::default();
+    for change in changes {
+        match change {
+            Change::Edit { pageid, ns, name } | Change::Move { pageid, ns, name } => {
+                if !tracked.contains(ns) {
+                    if known.contains(pageid) || changed.remove(pageid).is_some() {
+                        updates.fetched.remove(pageid);
+                        updates.removed.insert(*pageid);
+                    }
+                    continue;
+                }
+                let title = Title {
+                    id: *pageid,
+                    ns: *ns,
+                    ns_name: namespaces
+                        .iter()
+                        .find(|n| n.id == *ns)
+                        .map_or_else(String::new, |n| n.name.clone()),
+                    name: name.clone(),
+                };
+                changed.insert(*pageid, title);
+                updates.removed.remove(pageid);
+                updates.fetched.insert(*pageid);
+            }
+            Change::Delete { ns, name } => {
+                let deleted = changed
+                    .values()
+                    .chain(titles.iter().filter(|t| !changed.contains_key(&t.id)))
+                    .find(|t| t.ns == *ns && t.name == *name && !updates.removed.contains(&t.id))
+                    .map(|t| t.id);
+                if let Some(pageid) = deleted {
+                    changed.remove(&pageid);
+                    updates.fetched.remove(&pageid);
+                    updates.removed.insert(pageid);
+                }
+            }
+        }
+    }
+    titles.retain(|t| !updates.removed.contains(&t.id) && !changed.contains_key(&t.id));
+    for title in changed.into_values() {
+        insert_in_listing_order(titles, title, &ns_order);
+    }
+    updates
+}
+
+/// Return failures left after pages in `settled` were fetched again or removed
+///
+/// The first `earlier` failures were logged before, and are dropped if their pages are
+/// settled. Failures logged after them come from fetching again and are kept.
+fn remaining_failures(
+    failures: Vec<Failure>,
+    earlier: usize,
+    settled: &BTreeSet<u32>,
+) -> Vec<Failure> {
+    failures
+        .into_iter()
+        .enumerate()
+        .filter(|(i, f)| *i >= earlier || !settled.contains(&f.pageid))
+        .map(|(_, f)| f)
+        .collect()
+}
+
+/// Offset unit of the parsed docs, or None if nothing is parsed yet
+fn parsed_offset_unit(data: &Data) -> Result<Option<OffsetUnit>, Error> {
+    match data.parsed_text_files()?.next() {
+        Some(path) => Ok(Some(
+            data.load_parsed_text(parse_pageid(&path?))?.offset_unit,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Follow changes made on the wiki after the last crawl or `since`
+///
+/// Changed pages are fetched again, expanded again if the parse config uses expanded texts,
+/// and parsed again if docs have been parsed, and deleted pages are removed from every
+/// directory. Pages in the failures log are fetched again too,
+/// so that changes which failed to be fetched aren't lost, and only those which fail again
+/// stay in the log. `url` is the endpoint of api.php.
+pub fn update_markuped_text(
+    url: &str,
+    data_dir: &str,
+    since: Option<&str>,
+    client: &HttpClient,
+) -> Result<(), Error> {
+    let data = Data::new(data_dir);
+    let since = match since {
+        Some(since) => since.to_string(),
+        None => data
+            .load_crawl_state()?
+            .map(|s| s.last_crawled_at)
+            .ok_or_else(|| format_err!("no crawl time is recorded; specify it"))?,
+    };
+    let started_at = now_timestamp();
+    let mut titles = data.load_titles()?;
+    let namespaces = if data.namespace_file.exists() {
+        data.load_namespaces()?
+    } else {
+        Vec::new()
+    };
+    // recommended. see: https://www.mediawiki.org/wiki/Manual:Maxlag_parameter/ja
+    let query_client = QueryClient::new(url, 5, client);
+    let changes = request_recent_changes(&query_client, &since[..], &tracked_namespaces(&titles))?;
+    let mut updates = apply_changes(&mut titles, &namespaces, &changes);
+    let failures = data.load_failures()?;
+    let earlier_failures = failures.len();
+    let ids: HashSet<u32> = titles.iter().map(|t| t.id).collect();
+    let failed: BTreeSet<u32> = failures
+        .iter()
+        .map(|f| f.pageid)
+        .filter(|id| ids.contains(id) && !updates.fetched.contains(id))
+        .collect();
+    eprintln!(
+        "{} changes since {}: {} pages to fetch, {} pages to remove, {} failed pages to retry",
+        changes.len(),
+        since,
+        updates.fetched.len(),
+        updates.removed.len(),
+        failed.len()
+    );
+    updates.fetched.extend(failed);
+    for &pageid in &updates.removed {
+        data.remove_page(pageid)?;
+    }
+    let offset_unit = parsed_offset_unit(&data)?;
+    let options = ParseOptions::from_data(&data)?;
+    let names: HashMap<u32, String> = titles.iter().map(|t| (t.id, t.name.clone())).collect();
+    let pageids: Vec<u32> = updates.fetched.iter().cloned().collect();
+    let pb = ProgressBar::new(pageids.len() as u64);
+    let chunks: Vec<&[u32]> = pageids.chunks(MAX_PAGEIDS_PER_REQUEST).collect();
+    for_each_concurrently(&chunks, client.workers(), |chunk| {
+        for (pageid, res) in request_markuped_texts(&query_client, chunk, &data)? {
+            let (text, meta) = match res {
+                Ok(fetched) => fetched,
+                Err(_) => continue,
+            };
+            data.save_markuped_text(pageid, &text[..])?;
+            data.remove_expanded_text(pageid)?;
+            data.save_page_meta(pageid, &meta)?;
+            if options.expanded {
+                expand_markuped_text(url, &data, pageid, names.get(&pageid), client)?;
+            }
+            if let Some(unit) = offset_unit {
+                parse_markuped_text(&data, pageid, &options, unit)?;
+            }
+        }
+        pb.inc(chunk.len() as u64);
+        Ok(())
+    })?;
+    pb.finish_with_message("done");
+    let settled: BTreeSet<u32> = updates.fetched.union(&updates.removed).cloned().collect();
+    data.save_failures(&remaining_failures(
+        data.load_failures()?,
+        earlier_failures,
+        &settled,
+    ))?;
+    data.save_titles(&titles)?;
+    data.save_crawl_state(&CrawlState {
+        last_crawled_at: started_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use api::MWQueryApiResponse;
+
+    fn title(id: u32, ns: i32, ns_name: &str, name: &str) -> Title {
+        Title {
+            id,
+            ns,
+            ns_name: ns_name.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_recent_changes() -> Result<(), Error> {
+        let json = r#"{"continue":{"rccontinue":"20181102000000|1005","continue":"-||"},
+            "query":{"recentchanges":[
+                {"type":"edit","ns":0,"title":"Ore","pageid":3020,"revid":41235,"old_revid":41234,"rcid":1001},
+                {"type":"new","ns":0,"title":"Ruby","pageid":9000,"revid":50000,"old_revid":0,"rcid":1002},
+                {"type":"log","ns":0,"title":"Ore Block","pageid":0,"revid":0,"old_revid":0,"rcid":1003,
+                 "logid":1,"logtype":"delete","logaction":"delete","logparams":[]},
+                {"type":"log","ns":0,"title":"Iron ore","pageid":7315,"revid":0,"old_revid":0,"rcid":1004,
+                 "logid":2,"logtype":"move","logaction":"move","logparams":{"target_ns":0,"target_title":"Iron Ore"}},
+                {"type":"log","ns":6,"title":"File:Ore.png","pageid":8000,"revid":0,"old_revid":0,"rcid":1005,
+                 "logid":3,"logtype":"upload","logaction":"upload","logparams":{}}
+            ]}}"#;
+        let json: MWQueryApiResponse<MWRecentChangesQuery> = serde_json::from_str(json)?;
+        assert_eq!(json._continue.unwrap()["rccontinue"], "20181102000000|1005");
+        let changes: Vec<Change> = json
+            .query
+            .unwrap()
+            .recentchanges
+            .into_iter()
+            .filter_map(MWRecentChange::into_change)
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                Change::Edit {
+                    pageid: 3020,
+                    ns: 0,
+                    name: "Ore".to_string()
+                },
+                Change::Edit {
+                    pageid: 9000,
+                    ns: 0,
+                    name: "Ruby".to_string()
+                },
+                Change::Delete {
+                    ns: 0,
+                    name: "Ore Block".to_string()
+                },
+                Change::Move {
+                    pageid: 7315,
+                    ns: 0,
+                    name: "Iron Ore".to_string()
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let namespaces: Vec<Namespace> = serde_json::from_str(
+            r#"[{"id":0,"case":"first-letter","*":""},
+                {"id":14,"case":"first-letter","canonical":"Category","*":"Category"}]"#,
+        )
+        .unwrap();
+        // in listing order
+        let mut titles = vec![
+            title(7317, 0, "", "Gold ore"),
+            title(7315, 0, "", "Iron ore"),
+            title(3020, 0, "", "Ore"),
+            title(7316, 0, "", "Ore Block"),
+            title(5000, 14, "Category", "Category:Ore"),
+        ];
+        let changes = vec![
+            Change::Edit {
+                pageid: 3020,
+                ns: 0,
+                name: "Ore".to_string(),
+            },
+            Change::Edit {
+                pageid: 9000,
+                ns: 14,
+                name: "Category:Gems".to_string(),
+            },
+            Change::Delete {
+                ns: 0,
+                name: "Ore Block".to_string(),
+            },
+            Change::Move {
+                pageid: 7315,
+                ns: 0,
+                name: "Iron Ore".to_string(),
+            },
+            // out of the retrieved namespaces
+            Change::Edit {
+                pageid: 8000,
+                ns: 6,
+                name: "File:Ore.png".to_string(),
+            },
+            Change::Move {
+                pageid: 7317,
+                ns: 2,
+                name: "User:Alice/Gold ore".to_string(),
+            },
+        ];
+        let updates = apply_changes(&mut titles, &namespaces, &changes);
+        assert_eq!(
+            updates.fetched.into_iter().collect::<Vec<u32>>(),
+            vec![3020, 7315, 9000]
+        );
+        assert_eq!(
+            updates.removed.into_iter().collect::<Vec<u32>>(),
+            vec![7316, 7317]
+        );
+        let names: Vec<(u32, &str, &str)> = titles
+            .iter()
+            .map(|t| (t.id, &t.ns_name[..], &t.name[..]))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (7315, "", "Iron Ore"),
+                (3020, "", "Ore"),
+                (9000, "Category", "Category:Gems"),
+                (5000, "Category", "Category:Ore"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remaining_failures() {
+        let failure = |pageid| Failure::new(pageid, &format_err!("timed out"));
+        let failures = vec![failure(1), failure(2), failure(3), failure(2)];
+        let settled = vec![2, 3].into_iter().collect();
+        // page 2 failed again while 3 was fetched
+        let remaining: Vec<u32> = remaining_failures(failures, 3, &settled)
+            .iter()
+            .map(|f| f.pageid)
+            .collect();
+        assert_eq!(remaining, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_record_crawl_start() -> Result<(), Error> {
+        let data = Data::new("test_dir_crawl_start_4545");
+        record_crawl_start(&data, "2018-11-01T00:00:00Z", true)?;
+        // a resumed crawl keeps the earlier time
+        record_crawl_start(&data, "2018-11-02T00:00:00Z", true)?;
+        let state = data.load_crawl_state()?;
+        // a fresh crawl replaces it
+        record_crawl_start(&data, "2018-11-03T00:00:00Z", false)?;
+        let fresh_state = data.load_crawl_state()?;
+        fs::remove_dir_all(&data.base_dir)?;
+        assert_eq!(state.unwrap().last_crawled_at, "2018-11-01T00:00:00Z");
+        assert_eq!(fresh_state.unwrap().last_crawled_at, "2018-11-03T00:00:00Z");
+        Ok(())
+    }
+}